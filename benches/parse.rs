@@ -0,0 +1,137 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Per-file time of `get_fujifilm_settings` against handing the whole file to
+// kamadak-exif, which is what it used to do.  Runs on generated 30 MB JPEGs,
+// and on every file in $FILM_DETECT_BENCH_DIR when it is set.
+//
+// kamadak-exif stops after the EXIF segment too, so a camera JPEG with APP1
+// first (fujifilm_jpeg) should time the same either way.  The gain is in
+// skipping segments without reading them: other segments before APP1
+// (segments_before_exif) and files without EXIF, where kamadak-exif reads
+// the whole scan looking for one (jpeg_without_exif).
+//
+//     FILM_DETECT_BENCH_DIR=/mnt/archive/2024 cargo bench
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::path::{Path, PathBuf};
+
+const SCAN_SIZE: usize = 30 << 20;
+
+// 16 MB of the largest segments there are.
+const LEADING_SEGMENTS: usize = 256;
+const SEGMENT_SIZE: usize = 0xffff - 2;
+
+fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0xff, marker];
+    out.extend_from_slice(&((data.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+// An EXIF segment with nothing but a MakerNote that says Classic Chrome.
+fn exif_segment() -> Vec<u8> {
+    let mut maker_note = b"FUJIFILM".to_vec();
+    maker_note.extend_from_slice(&12u32.to_le_bytes());
+    maker_note.extend_from_slice(&1u16.to_le_bytes());
+    maker_note.extend_from_slice(&[0x01, 0x14, 3, 0, 1, 0, 0, 0, 0x00, 0x06, 0, 0]);
+    maker_note.extend_from_slice(&0u32.to_le_bytes());
+
+    let mut tiff = b"II*\0".to_vec();
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+    // IFD0, pointing at the EXIF IFD.
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&[0x69, 0x87, 4, 0, 1, 0, 0, 0]);
+    tiff.extend_from_slice(&26u32.to_le_bytes());
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    // The EXIF IFD with the MakerNote right after it.
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&[0x7c, 0x92, 7, 0]);
+    tiff.extend_from_slice(&(maker_note.len() as u32).to_le_bytes());
+    tiff.extend_from_slice(&44u32.to_le_bytes());
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    tiff.extend_from_slice(&maker_note);
+
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend_from_slice(&tiff);
+    segment(0xe1, &app1)
+}
+
+fn write_jpeg(name: &str, leading_segments: usize, exif: bool) -> PathBuf {
+    let mut data = vec![0xff, 0xd8];
+    for _ in 0..leading_segments {
+        data.extend_from_slice(&segment(0xe2, &[0x55; SEGMENT_SIZE]));
+    }
+    if exif {
+        data.extend_from_slice(&exif_segment());
+    }
+    data.extend_from_slice(&segment(0xda, &[1, 1, 0, 0, 0x3f, 0]));
+    // Scan data never contains an unstuffed 0xff.
+    data.resize(data.len() + SCAN_SIZE, 0x55);
+    data.extend_from_slice(&[0xff, 0xd9]);
+
+    let path = std::env::temp_dir().join(format!("film-detect-bench-{}.jpg", name));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+fn kamadak(path: &Path) {
+    let file = std::fs::File::open(path).unwrap();
+    let mut reader = std::io::BufReader::new(file);
+    let _ = exif::Reader::new().read_from_container(&mut reader);
+}
+
+fn fast_path(path: &Path) {
+    let _ = film_detect::get_fujifilm_settings(path);
+}
+
+fn bench_files(c: &mut Criterion, name: &str, files: &[PathBuf]) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(files.len() as u64));
+    group.bench_function("get_fujifilm_settings", |b| {
+        b.iter(|| files.iter().for_each(|f| fast_path(f)))
+    });
+    group.bench_function("read_from_container", |b| {
+        b.iter(|| files.iter().for_each(|f| kamadak(f)))
+    });
+    group.finish();
+}
+
+fn generated(c: &mut Criterion) {
+    bench_files(c, "fujifilm_jpeg", &[write_jpeg("fujifilm", 0, true)]);
+    bench_files(
+        c,
+        "segments_before_exif",
+        &[write_jpeg("segments", LEADING_SEGMENTS, true)],
+    );
+    bench_files(c, "jpeg_without_exif", &[write_jpeg("no-exif", 0, false)]);
+}
+
+fn archive(c: &mut Criterion) {
+    let Some(dir) = std::env::var_os("FILM_DETECT_BENCH_DIR") else {
+        return;
+    };
+    let files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .collect();
+    bench_files(c, "archive", &files);
+}
+
+criterion_group!(benches, generated, archive);
+criterion_main!(benches);
@@ -0,0 +1,73 @@
+# Types of the Python module built from src/python.rs.  The dicts are the
+# JSON `film-detect --json` prints.
+
+import os
+from typing import List, Optional, TypedDict, Union
+
+class WhiteBalanceFineTune(TypedDict):
+    red: int
+    blue: int
+
+class ShotInfo(TypedDict):
+    exposure_time: Optional[float]  # seconds
+    f_number: Optional[float]
+    iso: Optional[int]
+    exposure_compensation: Optional[float]  # EV
+    lens_model: Optional[str]
+    focal_length: Optional[float]  # mm
+    focal_length_35mm: Optional[int]
+
+class Body(TypedDict):
+    model: Optional[str]
+    serial_number: Optional[str]
+    internal_serial_number: Optional[str]
+    image_count: Optional[int]
+
+class Sequence(TypedDict):
+    number: int
+    auto_bracketing: str
+
+class _Settings(TypedDict):
+    white_balance: str
+    white_balance_fine_tune: WhiteBalanceFineTune
+    sharpness: int
+    noise_reduction: int
+    clarity: int
+    shadow: float  # in steps, which can be a half step, e.g. 1.5
+    highlight: float  # in steps, like shadow
+    grain_roughness: str
+    grain_size: str
+    color_chrome: str
+    color_chrome_fx_blue: str
+    film_mode: str
+    dynamic_range: str
+    color: Optional[int]  # None for monochrome film simulations
+    monochrome_filter: str
+
+class Settings(_Settings, total=False):
+    applied_dynamic_range: str
+    color_temperature: int  # Kelvin
+    advanced_filter: str
+    shot_info: ShotInfo
+    body: Body
+    sequence: Sequence
+    # Only when recipes were given; None when none of them matches.
+    recipe: Optional[str]
+
+class FilmDetectError(Exception):
+    """The photo has no Fujifilm settings, or they can't be read."""
+
+class Recipes:
+    """The recipes in a film simulation directory."""
+
+    @property
+    def names(self) -> List[str]: ...
+    def __len__(self) -> int: ...
+
+def load_recipes(dir: Union[str, os.PathLike[str]]) -> Recipes: ...
+def get_fujifilm_settings(
+    path: Union[str, os.PathLike[str]], recipes: Optional[Recipes] = None
+) -> Settings: ...
+def get_fujifilm_settings_from_bytes(
+    data: bytes, recipes: Optional[Recipes] = None
+) -> Settings: ...
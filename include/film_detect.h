@@ -0,0 +1,112 @@
+/*
+ * film detect
+ * Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ */
+
+#ifndef FILM_DETECT_H
+#define FILM_DETECT_H
+
+/* Generated by cbindgen from src/capi.rs.  Don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// What went wrong.  `film_detect_last_error` has the details.
+typedef enum FilmDetectError {
+  FILM_DETECT_ERROR_OK = 0,
+  FILM_DETECT_ERROR_IO = 1,
+  FILM_DETECT_ERROR_EXIF = 2,
+  FILM_DETECT_ERROR_NOT_A_FUJIFILM_FILE = 3,
+  FILM_DETECT_ERROR_UNEXPECTED_VALUE = 4,
+  FILM_DETECT_ERROR_INVALID_RECIPE = 5,
+  FILM_DETECT_ERROR_XMP = 6,
+  FILM_DETECT_ERROR_INVALID_JPEG = 7,
+  FILM_DETECT_ERROR_INVALID_TEMPLATE = 8,
+  FILM_DETECT_ERROR_INVALID_PROFILE = 9,
+  FILM_DETECT_ERROR_INVALID_EXIF_TOOL_JSON = 10,
+  FILM_DETECT_ERROR_CATALOG = 11,
+  // A null pointer, a string that isn't UTF-8 or an unknown field name.
+  FILM_DETECT_ERROR_INVALID_ARGUMENT = 12,
+  // A bug in film-detect.  Nothing was handed out.
+  FILM_DETECT_ERROR_INTERNAL = 13,
+} FilmDetectError;
+
+// The settings of one photo.
+typedef struct FilmDetectSettings FilmDetectSettings;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Reads the settings of the photo at `path`.
+//
+// # Safety
+//
+// `path` must be a NUL-terminated string and `out` a valid pointer.
+enum FilmDetectError film_detect_parse_path(const char *path, struct FilmDetectSettings **out);
+
+// Reads the settings of a photo in memory.
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes and `out` must be a valid
+// pointer.
+enum FilmDetectError film_detect_parse_buffer(const uint8_t *data,
+                                              size_t len,
+                                              struct FilmDetectSettings **out);
+
+// Writes the settings as the JSON `film-detect --json` prints.
+//
+// # Safety
+//
+// `settings` must come from one of the parse functions and `out` must be a
+// valid pointer.
+enum FilmDetectError film_detect_settings_json(const struct FilmDetectSettings *settings,
+                                               char **out);
+
+// Writes one setting by its JSON name, e.g. "film_mode" gives
+// "ClassicChrome".  Numbers come out as text, and objects such as
+// "shot_info" as JSON.  A setting the photo doesn't have gives an empty
+// string.
+//
+// # Safety
+//
+// `settings` must come from one of the parse functions, `name` must be a
+// NUL-terminated string and `out` must be a valid pointer.
+enum FilmDetectError film_detect_settings_field(const struct FilmDetectSettings *settings,
+                                                const char *name,
+                                                char **out);
+
+// Frees settings from one of the parse functions.  Null is ignored.
+//
+// # Safety
+//
+// `settings` must come from one of the parse functions and not be used
+// afterwards.
+void film_detect_settings_free(struct FilmDetectSettings *settings);
+
+// Frees a string the library returned.  Null is ignored.
+//
+// # Safety
+//
+// `s` must come from this library and not be used afterwards.
+void film_detect_string_free(char *s);
+
+// The message of the last error on this thread, e.g. "Error: not a Fujifilm
+// file".  It stays valid until the next call on the same thread fails.
+// Null only while the thread is shutting down.
+const char *film_detect_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* FILM_DETECT_H */
@@ -0,0 +1,243 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Which camera body took the photo.  The serial numbers identify the owner,
+// so they can be left out or hashed before results are shared.
+
+use crate::MakerNoteEntry;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "exif")]
+use exif::{In, Tag, Value};
+#[cfg(feature = "std")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use sha2::Sha256;
+
+// MakerNote tags.
+pub(crate) const INTERNAL_SERIAL_NUMBER: u16 = 0x0010;
+pub(crate) const IMAGE_COUNT: u16 = 0x1438;
+
+/// The body that took the photo.  Anything the camera didn't record is
+/// `None`.
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Body {
+    pub model: Option<String>,
+    // The serial number printed on the body.
+    pub serial_number: Option<String>,
+    // The one Fujifilm writes to the MakerNote.  It identifies the body even
+    // when the EXIF serial number is missing.
+    pub internal_serial_number: Option<String>,
+    // Roughly the shutter count: the number of frames the body has taken.
+    pub image_count: Option<u32>,
+}
+
+/// How `--redact` hides the body's identity.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redaction {
+    /// Drop everything that could identify the body and keep the model.
+    Omit,
+    /// Replace the serial numbers with an HMAC keyed with this secret salt,
+    /// so that photos from one body can still be grouped, and drop the image
+    /// count.  Serial numbers are short and guessable: without the salt
+    /// anyone could hash every serial and look the body up.  With it the
+    /// result is a pseudonym, as safe as the salt is secret.  Needs `std`.
+    #[cfg(feature = "std")]
+    Hash(String),
+}
+
+/// Whether a MakerNote tag says which body took the photo.
+pub fn identifies_body(tag: u16) -> bool {
+    matches!(tag, INTERNAL_SERIAL_NUMBER | IMAGE_COUNT)
+}
+
+/// Hides the MakerNote entries that identify the body, the way
+/// `Body::redact` hides the body's fields: `dump --redact` leaves them out,
+/// and `dump --redact=hash` hashes the internal serial number.
+pub fn redact_entries(entries: &mut Vec<MakerNoteEntry>, redaction: &Redaction) {
+    entries.retain(|e| match redaction {
+        #[cfg(feature = "std")]
+        Redaction::Hash(_) => e.tag != IMAGE_COUNT,
+        Redaction::Omit => !identifies_body(e.tag),
+    });
+    #[cfg(feature = "std")]
+    if let Redaction::Hash(salt) = redaction {
+        for entry in entries
+            .iter_mut()
+            .filter(|e| e.tag == INTERNAL_SERIAL_NUMBER)
+        {
+            let hashed = hash(&entry.value_string(), salt);
+            entry.data_type = 2;
+            entry.count = hashed.len() as u32;
+            entry.value = hashed.into_bytes();
+        }
+    }
+}
+
+#[cfg(feature = "exif")]
+fn text(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(v) => v
+            .first()
+            .map(|s| String::from_utf8_lossy(s).trim().to_string())
+            .filter(|s| !s.is_empty()),
+        _ => None,
+    }
+}
+
+// The first 12 hex digits of the HMAC-SHA256, enough to tell a pool of bodies
+// apart.
+#[cfg(feature = "std")]
+fn hash(value: &str, salt: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(salt.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(value.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .take(6)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl Body {
+    /// `None` when the file has none of the fields.
+    #[cfg(feature = "exif")]
+    pub(crate) fn from_exif(exif: &exif::Exif, entries: &[MakerNoteEntry]) -> Option<Self> {
+        let entry = |tag| entries.iter().find(|e| e.tag == tag);
+        let body = Body {
+            model: text(exif, Tag::Model),
+            serial_number: text(exif, Tag::BodySerialNumber),
+            internal_serial_number: entry(INTERNAL_SERIAL_NUMBER)
+                .map(|e| e.value_string())
+                .filter(|s| !s.is_empty()),
+            // The top bit is a flag.
+            image_count: entry(IMAGE_COUNT)
+                .and_then(|e| e.u16())
+                .map(|n| (n & 0x7fff) as u32),
+        };
+        (body != Body::default()).then_some(body)
+    }
+
+    pub fn redact(&mut self, redaction: &Redaction) {
+        match redaction {
+            Redaction::Omit => {
+                self.serial_number = None;
+                self.internal_serial_number = None;
+                self.image_count = None;
+            }
+            #[cfg(feature = "std")]
+            Redaction::Hash(salt) => {
+                let hash = |s: &str| hash(s, salt);
+                self.serial_number = self.serial_number.as_deref().map(hash);
+                self.internal_serial_number = self.internal_serial_number.as_deref().map(hash);
+                self.image_count = None;
+            }
+        }
+    }
+
+    /// The recorded values as label/value pairs.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        [
+            ("Camera", self.model.clone()),
+            ("Serial Number", self.serial_number.clone()),
+            (
+                "Internal Serial Number",
+                self.internal_serial_number.clone(),
+            ),
+            ("Image Count", self.image_count.map(|n| n.to_string())),
+        ]
+        .into_iter()
+        .filter_map(|(label, value)| Some((label, value?)))
+        .collect()
+    }
+}
+
+impl fmt::Display for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(label, value)| format!("{}: {}", label, value))
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(all(test, feature = "exif"))]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn reads_and_redacts_body() {
+        let entries = [
+            fixtures::ascii(INTERNAL_SERIAL_NUMBER, "FF02B4567890 Y12345678"),
+            fixtures::short(IMAGE_COUNT, 0x8000 | 1234),
+        ];
+        let maker_note = fixtures::maker_note(&entries);
+        let data = fixtures::jpeg_with_exif(&[
+            fixtures::ascii(0xa431, "1AB23456"),
+            fixtures::entry(
+                crate::container::MAKER_NOTES_TAG,
+                7,
+                maker_note.len() as u32,
+                maker_note,
+            ),
+        ]);
+        let exif = crate::container::read_exif(&mut std::io::Cursor::new(data)).unwrap();
+        let entries =
+            crate::makernote::read_entries(crate::container::maker_note(&exif).unwrap()).unwrap();
+        let body = Body::from_exif(&exif, &entries).unwrap();
+
+        assert_eq!(body.serial_number.as_deref(), Some("1AB23456"));
+        assert_eq!(
+            body.internal_serial_number.as_deref(),
+            Some("FF02B4567890 Y12345678")
+        );
+        assert_eq!(body.image_count, Some(1234));
+
+        let mut hashed = body.clone();
+        hashed.redact(&Redaction::Hash("salt".to_string()));
+        assert_eq!(hashed.serial_number.as_deref().map(str::len), Some(12));
+        assert_ne!(hashed.serial_number, body.serial_number);
+        assert_eq!(hashed.image_count, None);
+        assert!(!hashed.to_string().contains("1AB23456"));
+
+        // Another salt gives other pseudonyms.
+        let mut resalted = body.clone();
+        resalted.redact(&Redaction::Hash("pepper".to_string()));
+        assert_ne!(resalted.serial_number, hashed.serial_number);
+
+        let mut entries = entries;
+        redact_entries(&mut entries, &Redaction::Hash("salt".to_string()));
+        assert!(entries.iter().all(|e| e.tag != IMAGE_COUNT));
+        let serial = entries
+            .iter()
+            .find(|e| e.tag == INTERNAL_SERIAL_NUMBER)
+            .unwrap();
+        assert_eq!(Some(serial.value_string()), hashed.internal_serial_number);
+
+        let mut omitted = body;
+        omitted.redact(&Redaction::Omit);
+        assert_eq!(omitted, Body::default());
+    }
+}
@@ -0,0 +1,356 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The C API of the shared library.  include/film_detect.h is generated from
+// this file with cbindgen:
+//
+//     cbindgen --config cbindgen.toml --output include/film_detect.h
+//
+// Every function that can fail returns a FilmDetectError and hands its result
+// out through a pointer.  Settings and strings the library returns are owned
+// by the caller and freed with film_detect_settings_free and
+// film_detect_string_free.  The codes and the JSON names are part of the API:
+// new ones may be added, but existing ones don't change.  A panic never
+// unwinds into C; it comes back as the Internal code.
+
+use crate::{get_fujifilm_settings, get_fujifilm_settings_from_bytes, FilmError, FujifilmSettings};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+
+/// What went wrong.  `film_detect_last_error` has the details.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilmDetectError {
+    Ok = 0,
+    Io = 1,
+    Exif = 2,
+    NotAFujifilmFile = 3,
+    UnexpectedValue = 4,
+    InvalidRecipe = 5,
+    Xmp = 6,
+    InvalidJpeg = 7,
+    InvalidTemplate = 8,
+    InvalidProfile = 9,
+    InvalidExifToolJson = 10,
+    Catalog = 11,
+    /// A null pointer, a string that isn't UTF-8 or an unknown field name.
+    InvalidArgument = 12,
+    /// A bug in film-detect.  Nothing was handed out.
+    Internal = 13,
+}
+
+/// The settings of one photo.
+pub struct FilmDetectSettings {
+    settings: FujifilmSettings,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn fail(code: FilmDetectError, message: &str) -> FilmDetectError {
+    // Messages don't contain NULs, but a path in one could.
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    code
+}
+
+// Runs the body of an exported function.  Unwinding into C is undefined
+// behaviour, so a panic becomes an error code instead.
+fn guard(body: impl FnOnce() -> FilmDetectError) -> FilmDetectError {
+    std::panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        fail(
+            FilmDetectError::Internal,
+            &format!("internal error: {}", message),
+        )
+    })
+}
+
+fn film_error(error: FilmError) -> FilmDetectError {
+    let code = match error {
+        FilmError::IO(_) => FilmDetectError::Io,
+        FilmError::Exif(_) => FilmDetectError::Exif,
+        FilmError::NotAFujifilmFile => FilmDetectError::NotAFujifilmFile,
+        FilmError::UnexpectedValue(_) => FilmDetectError::UnexpectedValue,
+        FilmError::InvalidRecipe(_) => FilmDetectError::InvalidRecipe,
+        FilmError::Xmp(_) => FilmDetectError::Xmp,
+        FilmError::InvalidJpeg(_) => FilmDetectError::InvalidJpeg,
+        FilmError::InvalidTemplate(_) => FilmDetectError::InvalidTemplate,
+        FilmError::InvalidProfile(_) => FilmDetectError::InvalidProfile,
+        FilmError::InvalidExifToolJson(_) => FilmDetectError::InvalidExifToolJson,
+        FilmError::Catalog(_) => FilmDetectError::Catalog,
+    };
+    fail(code, &error.to_string())
+}
+
+fn output<T>(out: *mut T, value: T) -> FilmDetectError {
+    if out.is_null() {
+        return fail(FilmDetectError::InvalidArgument, "output pointer is null");
+    }
+    // SAFETY: the caller hands in a valid pointer to write the result to.
+    unsafe { out.write(value) };
+    FilmDetectError::Ok
+}
+
+fn settings_output(
+    result: Result<FujifilmSettings, FilmError>,
+    out: *mut *mut FilmDetectSettings,
+) -> FilmDetectError {
+    match result {
+        Ok(settings) => {
+            let settings = Box::into_raw(Box::new(FilmDetectSettings { settings }));
+            let code = output(out, settings);
+            if code != FilmDetectError::Ok {
+                // SAFETY: allocated just above and never handed out.
+                drop(unsafe { Box::from_raw(settings) });
+            }
+            code
+        }
+        Err(e) => film_error(e),
+    }
+}
+
+fn string_output(value: String, out: *mut *mut c_char) -> FilmDetectError {
+    match CString::new(value) {
+        Ok(value) => {
+            let value = value.into_raw();
+            let code = output(out, value);
+            if code != FilmDetectError::Ok {
+                // SAFETY: allocated just above and never handed out.
+                drop(unsafe { CString::from_raw(value) });
+            }
+            code
+        }
+        Err(_) => fail(FilmDetectError::InvalidArgument, "string contains NUL"),
+    }
+}
+
+// SAFETY: `s` is null or a NUL-terminated string.
+unsafe fn str_argument<'a>(s: *const c_char, name: &str) -> Result<&'a str, FilmDetectError> {
+    if s.is_null() {
+        return Err(fail(
+            FilmDetectError::InvalidArgument,
+            &format!("{} is null", name),
+        ));
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| {
+        fail(
+            FilmDetectError::InvalidArgument,
+            &format!("{} isn't UTF-8", name),
+        )
+    })
+}
+
+/// Reads the settings of the photo at `path`.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn film_detect_parse_path(
+    path: *const c_char,
+    out: *mut *mut FilmDetectSettings,
+) -> FilmDetectError {
+    guard(|| match str_argument(path, "path") {
+        Ok(path) => settings_output(get_fujifilm_settings(Path::new(path)), out),
+        Err(code) => code,
+    })
+}
+
+/// Reads the settings of a photo in memory.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` must be a valid
+/// pointer.
+#[no_mangle]
+pub unsafe extern "C" fn film_detect_parse_buffer(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut FilmDetectSettings,
+) -> FilmDetectError {
+    guard(|| {
+        if data.is_null() {
+            return fail(FilmDetectError::InvalidArgument, "data is null");
+        }
+        let data = std::slice::from_raw_parts(data, len);
+        settings_output(get_fujifilm_settings_from_bytes(data), out)
+    })
+}
+
+/// Writes the settings as the JSON `film-detect --json` prints.
+///
+/// # Safety
+///
+/// `settings` must come from one of the parse functions and `out` must be a
+/// valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn film_detect_settings_json(
+    settings: *const FilmDetectSettings,
+    out: *mut *mut c_char,
+) -> FilmDetectError {
+    guard(|| {
+        let Some(settings) = settings.as_ref() else {
+            return fail(FilmDetectError::InvalidArgument, "settings is null");
+        };
+        string_output(serde_json::json!(settings.settings).to_string(), out)
+    })
+}
+
+/// Writes one setting by its JSON name, e.g. "film_mode" gives
+/// "ClassicChrome".  Numbers come out as text, and objects such as
+/// "shot_info" as JSON.  A setting the photo doesn't have gives an empty
+/// string.
+///
+/// # Safety
+///
+/// `settings` must come from one of the parse functions, `name` must be a
+/// NUL-terminated string and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn film_detect_settings_field(
+    settings: *const FilmDetectSettings,
+    name: *const c_char,
+    out: *mut *mut c_char,
+) -> FilmDetectError {
+    guard(|| {
+        let Some(settings) = settings.as_ref() else {
+            return fail(FilmDetectError::InvalidArgument, "settings is null");
+        };
+        let name = match str_argument(name, "name") {
+            Ok(name) => name,
+            Err(code) => return code,
+        };
+
+        let value = match serde_json::json!(settings.settings).get(name) {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            Some(serde_json::Value::Null) => String::new(),
+            Some(value) => value.to_string(),
+            None => {
+                return fail(
+                    FilmDetectError::InvalidArgument,
+                    &format!("unknown setting {}", name),
+                )
+            }
+        };
+        string_output(value, out)
+    })
+}
+
+/// Frees settings from one of the parse functions.  Null is ignored.
+///
+/// # Safety
+///
+/// `settings` must come from one of the parse functions and not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn film_detect_settings_free(settings: *mut FilmDetectSettings) {
+    if !settings.is_null() {
+        guard(|| {
+            drop(Box::from_raw(settings));
+            FilmDetectError::Ok
+        });
+    }
+}
+
+/// Frees a string the library returned.  Null is ignored.
+///
+/// # Safety
+///
+/// `s` must come from this library and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn film_detect_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        guard(|| {
+            drop(CString::from_raw(s));
+            FilmDetectError::Ok
+        });
+    }
+}
+
+/// The message of the last error on this thread, e.g. "Error: not a Fujifilm
+/// file".  It stays valid until the next call on the same thread fails.
+/// Null only while the thread is shutting down.
+#[no_mangle]
+pub extern "C" fn film_detect_last_error() -> *const c_char {
+    LAST_ERROR
+        .try_with(|last| last.borrow().as_ptr())
+        .unwrap_or(std::ptr::null())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, FilmMode};
+    use std::ptr;
+
+    unsafe fn take_string(s: *mut c_char) -> String {
+        let value = CStr::from_ptr(s).to_str().unwrap().to_string();
+        film_detect_string_free(s);
+        value
+    }
+
+    #[test]
+    fn parses_through_the_c_api() {
+        let mut settings = FujifilmSettings::new();
+        settings.film_mode = FilmMode::ClassicChrome;
+        let photo = fixtures::jpeg(&settings);
+
+        unsafe {
+            let mut parsed = ptr::null_mut();
+            let code = film_detect_parse_buffer(photo.as_ptr(), photo.len(), &mut parsed);
+            assert_eq!(code, FilmDetectError::Ok);
+
+            let mut value = ptr::null_mut();
+            let code = film_detect_settings_field(parsed, c"film_mode".as_ptr(), &mut value);
+            assert_eq!(code, FilmDetectError::Ok);
+            assert_eq!(take_string(value), "ClassicChrome");
+
+            let code = film_detect_settings_field(parsed, c"color".as_ptr(), &mut value);
+            assert_eq!(code, FilmDetectError::Ok);
+            assert_eq!(take_string(value), "0");
+
+            let code = film_detect_settings_field(parsed, c"lens".as_ptr(), &mut value);
+            assert_eq!(code, FilmDetectError::InvalidArgument);
+
+            let code = film_detect_settings_json(parsed, &mut value);
+            assert_eq!(code, FilmDetectError::Ok);
+            let json: serde_json::Value = serde_json::from_str(&take_string(value)).unwrap();
+            assert_eq!(json["film_mode"], "ClassicChrome");
+            film_detect_settings_free(parsed);
+
+            let mut parsed = ptr::null_mut();
+            let code = film_detect_parse_path(c"/nonexistent.JPG".as_ptr(), &mut parsed);
+            assert_eq!(code, FilmDetectError::Io);
+            assert!(parsed.is_null());
+            let message = CStr::from_ptr(film_detect_last_error()).to_str().unwrap();
+            assert!(message.starts_with("I/O error"));
+        }
+    }
+
+    #[test]
+    fn panics_become_internal_errors() {
+        assert_eq!(guard(|| panic!("boom")), FilmDetectError::Internal);
+        let message = unsafe { CStr::from_ptr(film_detect_last_error()) };
+        assert_eq!(message.to_str().unwrap(), "internal error: boom");
+    }
+}
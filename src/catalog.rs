@@ -0,0 +1,513 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A SQLite catalog of parsed files, so that reports over a large archive
+// don't have to open every photo again.  A file is read again only when its
+// size or modification time changed, and parsed again only when its contents
+// did or when the catalog was made by a different version of film-detect.
+// The settings are stored as the JSON `--json` prints, which SQLite's
+// json_extract can query directly:
+//
+//     SELECT json_extract(settings, '$.film_mode'), count(*)
+//     FROM files GROUP BY 1;
+
+use crate::files::collect_files;
+use crate::{get_fujifilm_settings_from_bytes, FilmError, FujifilmSettings};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Kept in SQLite's user_version.  Bump it whenever the tables change.
+const SCHEMA_VERSION: i64 = 2;
+
+// Any release may read files differently or change the stored JSON, so a
+// catalog made by another one is dropped and every file parsed again.
+const PARSER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS parser (
+        version TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        -- 0 when the file couldn't be read.
+        size INTEGER NOT NULL,
+        -- Nanoseconds since the Unix epoch.
+        modified INTEGER NOT NULL,
+        -- SHA-256 of the whole file, NULL when it couldn't be read.
+        hash TEXT,
+        -- Exactly one of these is set.
+        settings TEXT,
+        error TEXT
+    );
+";
+
+/// One file in the catalog.
+#[derive(Debug)]
+pub struct CatalogEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: i64,
+    pub hash: Option<String>,
+    // Why the file couldn't be parsed, as `FilmError` shows it.
+    pub settings: Result<FujifilmSettings, String>,
+}
+
+/// What a scan did.
+#[derive(Debug, Default, PartialEq)]
+pub struct ScanSummary {
+    pub parsed: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+    // Files that were in the catalog but are gone.
+    pub removed: usize,
+}
+
+pub struct Catalog {
+    db: Connection,
+}
+
+enum Scanned {
+    Unchanged,
+    Parsed {
+        size: u64,
+        modified: i64,
+        hash: String,
+        settings: FujifilmSettings,
+    },
+    // `hash` is `None` when the file couldn't be read.
+    Failed {
+        size: u64,
+        modified: i64,
+        hash: Option<String>,
+        error: FilmError,
+    },
+}
+
+// Reads and parses `file` unless the catalog already has it.  Only errors
+// from the catalog itself are returned; the file's own go into `Failed`.
+fn scan_file(tx: &Transaction, file: &Path, key: &str) -> Result<Scanned, FilmError> {
+    let failed = |size, modified, error: std::io::Error| {
+        Ok(Scanned::Failed {
+            size,
+            modified,
+            hash: None,
+            error: error.into(),
+        })
+    };
+
+    let metadata = match std::fs::metadata(file) {
+        Ok(metadata) => metadata,
+        Err(e) => return failed(0, 0, e),
+    };
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or_default();
+
+    let known: Option<(u64, i64, Option<String>)> = tx
+        .query_row(
+            "SELECT size, modified, hash FROM files WHERE path = ?1",
+            params![key],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    let known_hash = known.and_then(|(s, m, h)| h.map(|h| (s == size && m == modified, h)));
+    if known_hash.as_ref().is_some_and(|(same, _)| *same) {
+        return Ok(Scanned::Unchanged);
+    }
+
+    let data = match std::fs::read(file) {
+        Ok(data) => data,
+        Err(e) => return failed(size, modified, e),
+    };
+    let hash = hash(&data);
+    if known_hash.is_some_and(|(_, h)| h == hash) {
+        // Touched, but the same file.
+        tx.execute(
+            "UPDATE files SET modified = ?2 WHERE path = ?1",
+            params![key, modified],
+        )?;
+        return Ok(Scanned::Unchanged);
+    }
+
+    Ok(match get_fujifilm_settings_from_bytes(&data) {
+        Ok(settings) => Scanned::Parsed {
+            size,
+            modified,
+            hash,
+            settings,
+        },
+        Err(error) => Scanned::Failed {
+            size,
+            modified,
+            hash: Some(hash),
+            error,
+        },
+    })
+}
+
+fn hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl Catalog {
+    /// Opens the catalog at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, FilmError> {
+        let mut db = Connection::open(path)?;
+        let schema: i64 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let parser: Option<String> = if schema == SCHEMA_VERSION {
+            db.query_row("SELECT version FROM parser", [], |row| row.get(0))
+                .optional()?
+        } else {
+            None
+        };
+
+        if parser.as_deref() != Some(PARSER_VERSION) {
+            let tx = db.transaction()?;
+            tx.execute_batch("DROP TABLE IF EXISTS files; DROP TABLE IF EXISTS parser;")?;
+            tx.execute_batch(SCHEMA)?;
+            tx.execute(
+                "INSERT INTO parser (version) VALUES (?1)",
+                params![PARSER_VERSION],
+            )?;
+            tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+            tx.commit()?;
+        }
+        Ok(Catalog { db })
+    }
+
+    /// Brings the catalog up to date with the files and directories in
+    /// `paths`.  Directories are scanned recursively for photos, and files
+    /// under them that are gone are removed from the catalog.  A file that
+    /// can't be read is recorded with its error, like one that can't be
+    /// parsed, and tried again on the next scan.  So is a directory that
+    /// can't be read; what the catalog has under it is kept.
+    pub fn scan(&mut self, paths: &[PathBuf]) -> Result<ScanSummary, FilmError> {
+        let mut files = vec![];
+        let mut unreadable = vec![];
+        for path in paths {
+            if let Err(e) = collect_files(path, &mut files, &mut unreadable) {
+                unreadable.push((path.clone(), e));
+            }
+        }
+
+        let mut summary = ScanSummary::default();
+        let mut seen = HashSet::new();
+        let tx = self.db.transaction()?;
+
+        let unreadable: Vec<PathBuf> = unreadable
+            .into_iter()
+            .map(|(dir, error)| {
+                let dir = std::fs::canonicalize(&dir).unwrap_or(dir);
+                let key = dir.to_string_lossy().into_owned();
+                summary.failed += 1;
+                tx.execute(
+                    "INSERT OR REPLACE INTO files (path, size, modified, hash, settings, error)
+                     VALUES (?1, 0, 0, NULL, NULL, ?2)",
+                    params![key, error.to_string()],
+                )?;
+                seen.insert(key);
+                Ok(dir)
+            })
+            .collect::<Result<_, FilmError>>()?;
+
+        for file in files {
+            let file = std::fs::canonicalize(&file).unwrap_or(file);
+            let key = file.to_string_lossy().into_owned();
+            seen.insert(key.clone());
+
+            let (size, modified, hash, settings, error) = match scan_file(&tx, &file, &key)? {
+                Scanned::Unchanged => {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                Scanned::Parsed {
+                    size,
+                    modified,
+                    hash,
+                    settings,
+                } => {
+                    summary.parsed += 1;
+                    (
+                        size,
+                        modified,
+                        Some(hash),
+                        serde_json::to_string(&settings).ok(),
+                        None,
+                    )
+                }
+                Scanned::Failed {
+                    size,
+                    modified,
+                    hash,
+                    error,
+                } => {
+                    summary.failed += 1;
+                    (size, modified, hash, None, Some(error.to_string()))
+                }
+            };
+            tx.execute(
+                "INSERT OR REPLACE INTO files (path, size, modified, hash, settings, error)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![key, size, modified, hash, settings, error],
+            )?;
+        }
+
+        let roots: Vec<PathBuf> = paths
+            .iter()
+            .map(|path| std::fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+            .collect();
+        let known: Vec<String> = tx
+            .prepare("SELECT path FROM files")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        for key in known {
+            let path = Path::new(&key);
+            // A directory that has become readable again is scanned, and
+            // its own row goes with the files that are gone.
+            if !seen.contains(&key)
+                && roots.iter().any(|root| path.starts_with(root))
+                && !unreadable.iter().any(|dir| path.starts_with(dir))
+                && !path.is_file()
+            {
+                tx.execute("DELETE FROM files WHERE path = ?1", params![key])?;
+                summary.removed += 1;
+            }
+        }
+
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    /// Every file in the catalog, sorted by path.  The files themselves
+    /// aren't touched.
+    pub fn entries(&self) -> Result<Vec<CatalogEntry>, FilmError> {
+        let mut statement = self.db.prepare(
+            "SELECT path, size, modified, hash, settings, error FROM files ORDER BY path",
+        )?;
+        let rows = statement.query_map([], |row| {
+            let settings: Option<String> = row.get(4)?;
+            let error: Option<String> = row.get(5)?;
+            Ok(CatalogEntry {
+                path: PathBuf::from(row.get::<_, String>(0)?),
+                size: row.get(1)?,
+                modified: row.get(2)?,
+                hash: row.get(3)?,
+                settings: match settings {
+                    Some(json) => serde_json::from_str(&json).map_err(|e| e.to_string()),
+                    None => Err(error.unwrap_or_default()),
+                },
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, FilmMode};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("film-detect-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("photos")).unwrap();
+        dir
+    }
+
+    fn write_photo(path: &Path, film_mode: FilmMode) {
+        let mut settings = FujifilmSettings::new();
+        settings.film_mode = film_mode;
+        std::fs::write(path, fixtures::jpeg(&settings)).unwrap();
+    }
+
+    #[test]
+    fn scans_only_changed_files() {
+        let dir = temp_dir("catalog");
+        let photos = dir.join("photos");
+        write_photo(&photos.join("1.JPG"), FilmMode::Velvia);
+        write_photo(&photos.join("2.JPG"), FilmMode::Astia);
+        std::fs::write(photos.join("notes.txt"), "not a photo").unwrap();
+        std::fs::write(photos.join("3.JPG"), "not a photo either").unwrap();
+
+        let mut catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let summary = catalog.scan(std::slice::from_ref(&photos)).unwrap();
+        assert_eq!(
+            summary,
+            ScanSummary {
+                parsed: 2,
+                unchanged: 0,
+                failed: 1,
+                removed: 0,
+            }
+        );
+
+        // Same contents with a new modification time, and a new photo.
+        let first = std::fs::File::options()
+            .write(true)
+            .open(photos.join("1.JPG"))
+            .unwrap();
+        first
+            .set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1))
+            .unwrap();
+        write_photo(&photos.join("2.JPG"), FilmMode::Eterna);
+        let second = std::fs::File::options()
+            .write(true)
+            .open(photos.join("2.JPG"))
+            .unwrap();
+        second
+            .set_modified(UNIX_EPOCH + std::time::Duration::from_secs(2))
+            .unwrap();
+
+        let mut catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let summary = catalog.scan(&[photos]).unwrap();
+        assert_eq!(
+            summary,
+            ScanSummary {
+                parsed: 1,
+                unchanged: 2,
+                failed: 0,
+                removed: 0,
+            }
+        );
+
+        let entries = catalog.entries().unwrap();
+        let film_modes: Vec<_> = entries
+            .iter()
+            .map(|e| e.settings.as_ref().map(|s| *s.film_mode()).ok())
+            .collect();
+        assert_eq!(
+            film_modes,
+            [Some(FilmMode::Velvia), Some(FilmMode::Eterna), None]
+        );
+        assert_eq!(entries[0].hash.as_ref().unwrap().len(), 64);
+    }
+
+    #[test]
+    fn records_unreadable_files_and_removes_gone_ones() {
+        let dir = temp_dir("catalog-gone");
+        let photos = dir.join("photos");
+        write_photo(&photos.join("1.JPG"), FilmMode::Velvia);
+        write_photo(&photos.join("2.JPG"), FilmMode::Astia);
+        std::os::unix::fs::symlink(photos.join("missing.JPG"), photos.join("3.JPG")).unwrap();
+
+        let mut catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let summary = catalog.scan(std::slice::from_ref(&photos)).unwrap();
+        assert_eq!((summary.parsed, summary.failed), (2, 1));
+        let entries = catalog.entries().unwrap();
+        assert_eq!(entries[2].hash, None);
+        assert!(entries[2].settings.is_err());
+
+        // Files that couldn't be read are tried again.
+        std::fs::remove_file(photos.join("2.JPG")).unwrap();
+        let summary = catalog.scan(std::slice::from_ref(&photos)).unwrap();
+        assert_eq!(
+            summary,
+            ScanSummary {
+                parsed: 0,
+                unchanged: 1,
+                failed: 1,
+                removed: 1,
+            }
+        );
+        assert_eq!(catalog.entries().unwrap().len(), 2);
+
+        // Another root leaves these alone.
+        let other = dir.join("other");
+        std::fs::create_dir(&other).unwrap();
+        std::fs::remove_file(photos.join("1.JPG")).unwrap();
+        assert_eq!(catalog.scan(&[other]).unwrap().removed, 0);
+        assert_eq!(catalog.entries().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn skips_unreadable_directories_and_symlink_loops() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("catalog-unreadable");
+        let photos = dir.join("photos");
+        let locked = photos.join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        write_photo(&photos.join("1.JPG"), FilmMode::Velvia);
+        write_photo(&locked.join("2.JPG"), FilmMode::Astia);
+        std::os::unix::fs::symlink(&photos, photos.join("loop")).unwrap();
+
+        let mut catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let summary = catalog.scan(std::slice::from_ref(&photos)).unwrap();
+        assert_eq!((summary.parsed, summary.failed), (2, 0));
+
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads it anyway.
+        if std::fs::read_dir(&locked).is_err() {
+            let summary = catalog.scan(std::slice::from_ref(&photos)).unwrap();
+            assert_eq!(
+                summary,
+                ScanSummary {
+                    parsed: 0,
+                    unchanged: 1,
+                    failed: 1,
+                    removed: 0,
+                }
+            );
+            assert_eq!(catalog.entries().unwrap().len(), 3);
+        }
+
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let summary = catalog.scan(std::slice::from_ref(&photos)).unwrap();
+        assert_eq!((summary.unchanged, summary.failed), (2, 0));
+        assert_eq!(catalog.entries().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn parses_again_after_an_upgrade() {
+        let dir = temp_dir("catalog-version");
+        let photos = dir.join("photos");
+        write_photo(&photos.join("1.JPG"), FilmMode::Velvia);
+
+        let mut catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        catalog.scan(std::slice::from_ref(&photos)).unwrap();
+        catalog
+            .db
+            .execute("UPDATE parser SET version = '0.0.0'", [])
+            .unwrap();
+        drop(catalog);
+
+        let mut catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        assert_eq!(
+            catalog.scan(std::slice::from_ref(&photos)).unwrap().parsed,
+            1
+        );
+
+        // A catalog with the old table layout.
+        catalog
+            .db
+            .pragma_update(None, "user_version", SCHEMA_VERSION - 1)
+            .unwrap();
+        drop(catalog);
+
+        let mut catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        assert_eq!(catalog.scan(&[photos]).unwrap().parsed, 1);
+    }
+}
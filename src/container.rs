@@ -0,0 +1,98 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Reading settings out of files: JPEGs, MPOs, HEIFs, TIFFs and anything else
+// kamadak-exif understands, which doesn't include RAFs.  The decoding itself
+// happens on the MakerNote bytes, in the crate root.
+
+use crate::{
+    jpeg, makernote, settings_from_entries, Body, FilmError, FujifilmSettings, MakerNoteEntry,
+    ShotInfo,
+};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+pub(crate) const MAKER_NOTES_TAG: u16 = 37500;
+
+// Reads the EXIF data of a JPEG, TIFF or any other container kamadak-exif
+// understands.
+pub(crate) fn read_exif<R: io::BufRead + io::Seek>(
+    reader: &mut R,
+) -> Result<exif::Exif, FilmError> {
+    let exifreader = exif::Reader::new();
+    Ok(exifreader.read_from_container(reader)?)
+}
+
+// Finds the raw MakerNote bytes in the EXIF data.
+pub(crate) fn maker_note(exif: &exif::Exif) -> Result<&[u8], FilmError> {
+    for field in exif.fields() {
+        if field.tag.number() == MAKER_NOTES_TAG {
+            if let exif::Value::Undefined(ref v, _index) = field.value {
+                return Ok(v);
+            }
+        }
+    }
+
+    Err(FilmError::NotAFujifilmFile)
+}
+
+pub(crate) fn open(path: &Path) -> Result<io::BufReader<File>, FilmError> {
+    let file = File::open(path)?;
+    Ok(io::BufReader::new(file))
+}
+
+/// Lists every MakerNote entry in the file, including the ones we don't
+/// decode.  Useful for debugging and for reporting new tags upstream.
+pub fn get_maker_note_entries(path: &Path) -> Result<Vec<MakerNoteEntry>, FilmError> {
+    let exif = exif_from_reader(&mut open(path)?)?;
+    makernote::read_entries(maker_note(&exif)?)
+}
+
+pub fn get_fujifilm_settings(path: &Path) -> Result<FujifilmSettings, FilmError> {
+    settings_from_reader(&mut open(path)?)
+}
+
+/// Reads the settings of a photo that is already in memory, e.g. an upload or
+/// a file dropped into a web page.
+pub fn get_fujifilm_settings_from_bytes(data: &[u8]) -> Result<FujifilmSettings, FilmError> {
+    settings_from_reader(&mut io::Cursor::new(data))
+}
+
+fn settings_from_reader<R: io::BufRead + io::Seek>(
+    reader: &mut R,
+) -> Result<FujifilmSettings, FilmError> {
+    settings_from_exif(&exif_from_reader(reader)?)
+}
+
+// JPEGs only need their EXIF segment.  Everything else goes through
+// kamadak-exif.
+pub(crate) fn exif_from_reader<R: io::BufRead + io::Seek>(
+    reader: &mut R,
+) -> Result<exif::Exif, FilmError> {
+    match jpeg::read_exif_segment(reader)? {
+        Some(tiff) => Ok(exif::Reader::new().read_raw(tiff)?),
+        None => read_exif(reader),
+    }
+}
+
+pub(crate) fn settings_from_exif(exif: &exif::Exif) -> Result<FujifilmSettings, FilmError> {
+    let entries = makernote::read_entries(maker_note(exif)?)?;
+    let mut settings = settings_from_entries(&entries)?;
+    settings.shot_info = ShotInfo::from_exif(exif);
+    settings.body = Body::from_exif(exif, &entries);
+    Ok(settings)
+}
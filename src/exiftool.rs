@@ -0,0 +1,655 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `exiftool -j -G` dumps: an array with one object per file, keyed by
+// "Group:TagName".  ExifTool prints the MakerNote values for people, e.g.
+// "+1 (medium hard)", so we turn them back into the values the camera wrote
+// and decode those like a MakerNote of our own.  Dumps made with -n already
+// have the raw numbers.
+
+use crate::makernote::MakerNoteEntry;
+use crate::setting::unexpected;
+use crate::{
+    settings_from_entries, AdvancedFilter, AutoBracketing, Body, Clarity, ColorChrome,
+    ColorChromeFxBlue, DynamicRange, FilmError, FilmMode, FujifilmSettings, GrainRoughness,
+    GrainSize, NoiseReduction, Saturation, Setting, Sharpness, ShotInfo, ToneCurve, WhiteBalance,
+};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+// The group ExifTool puts the MakerNote in: MakerNotes with -G, FujiFilm
+// with -G1.
+const MAKER_NOTE_GROUPS: &[&str] = &["MakerNotes", "FujiFilm"];
+const EXIF_GROUPS: &[&str] = &["EXIF", "IFD0", "ExifIFD"];
+
+const WHITE_BALANCE_FINE_TUNE: u16 = 0x100a;
+const ADVANCED_FILTER: u16 = 0x1201;
+const FILM_MODE: u16 = 0x1401;
+
+// No advanced filter has this value, so it reads back as an unknown one.
+const UNKNOWN_ADVANCED_FILTER: i32 = -1;
+
+type Convert = fn(&str) -> Option<i32>;
+
+// (ExifTool name, tag, how to read a printed value, whether a JSON number
+// can be a printed value rather than a raw one)
+const TAGS: &[(&str, u16, Convert, bool)] = &[
+    ("Sharpness", 0x1001, step::<Sharpness>, false),
+    ("WhiteBalance", 0x1002, choice::<WhiteBalance>, false),
+    ("Saturation", 0x1003, step::<Saturation>, false),
+    ("ColorTemperature", 0x1005, number, false),
+    (
+        "HighISONoiseReduction",
+        0x100e,
+        step::<NoiseReduction>,
+        false,
+    ),
+    ("Clarity", 0x100f, step::<Clarity>, true),
+    ("ShadowTone", 0x1040, step::<ToneCurve>, true),
+    ("HighlightTone", 0x1041, step::<ToneCurve>, true),
+    (
+        "GrainEffectRoughness",
+        0x1047,
+        choice::<GrainRoughness>,
+        false,
+    ),
+    ("ColorChromeEffect", 0x1048, choice::<ColorChrome>, false),
+    ("GrainEffectSize", 0x104c, choice::<GrainSize>, false),
+    (
+        "ColorChromeFXBlue",
+        0x104e,
+        choice::<ColorChromeFxBlue>,
+        false,
+    ),
+    ("AutoBracketing", 0x1100, choice::<AutoBracketing>, false),
+    ("SequenceNumber", 0x1101, number, false),
+    ("AdvancedFilter", 0x1201, choice::<AdvancedFilter>, false),
+    ("FilmMode", 0x1401, choice::<FilmMode>, false),
+    ("DynamicRangeSetting", 0x1402, dynamic_range_setting, false),
+    ("DevelopmentDynamicRange", 0x1403, number, false),
+    ("AutoDynamicRange", 0x140b, number, false),
+];
+
+// Printed values that aren't one of our labels.
+const ALIASES: &[(u16, &str, i32)] = &[
+    (0x1002, "Auto (white priority)", 0x1),
+    (0x1002, "Auto (ambiance priority)", 0x2),
+    (0x1003, "None (B&W)", 0x300),
+    (0x1003, "B&W Red Filter", 0x301),
+    (0x1003, "B&W Yellow Filter", 0x302),
+    (0x1003, "B&W Green Filter", 0x303),
+    (0x1003, "B&W Sepia", 0x310),
+    (0x1003, "Acros", 0x500),
+    (0x1003, "Acros Red Filter", 0x501),
+    (0x1003, "Acros Yellow Filter", 0x502),
+    (0x1003, "Acros Green Filter", 0x503),
+    (0x1100, "No flash & flash", 2),
+    (0x1201, "Hi Key", 0x20000),
+    (0x1201, "Low Key", 0x90000),
+    (0x1201, "Partial Color Red", 0x60001),
+    (0x1201, "Partial Color Yellow", 0x60002),
+    (0x1201, "Partial Color Green", 0x60003),
+    (0x1201, "Partial Color Blue", 0x60004),
+    (0x1201, "Partial Color Orange", 0x60005),
+    (0x1201, "Partial Color Purple", 0x60006),
+    (0x1401, "F0/Standard (Provia)", 0x0),
+    (
+        0x1401,
+        "F1b/Studio Portrait Smooth Skin Tone (Astia)",
+        0x120,
+    ),
+    (0x1401, "F4/Velvia", 0x400),
+    (0x1401, "Pro Neg. Std", 0x500),
+    (0x1401, "Pro Neg. Hi", 0x501),
+    (0x1401, "Bleach Bypass", 0x900),
+];
+
+/// The settings of one file in an ExifTool dump.
+#[derive(Debug)]
+pub struct ExifToolRecord {
+    pub source_file: PathBuf,
+    pub settings: Result<FujifilmSettings, FilmError>,
+}
+
+// "+1 (medium hard)" is step +1.
+fn step<T: Setting<Raw = i32>>(value: &str) -> Option<i32> {
+    let step = value.split(" (").next()?.trim();
+    T::parse(step).and_then(|v| v.encode())
+}
+
+fn choice<T: Setting<Raw = i32>>(value: &str) -> Option<i32> {
+    T::parse(value).and_then(|v| v.encode())
+}
+
+// "5200", "200%", "23.0 mm"
+fn number(value: &str) -> Option<i32> {
+    let number = value.split_whitespace().next()?.trim_end_matches('%');
+    number.parse::<f64>().ok().map(|n| n.round() as i32)
+}
+
+// Only Auto matters: anything else is a manual setting.
+fn dynamic_range_setting(value: &str) -> Option<i32> {
+    Some(if value.eq_ignore_ascii_case("Auto") {
+        0
+    } else {
+        1
+    })
+}
+
+// "1/250", "-2/3", "2.8", "23.0 mm"
+fn fraction(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => {
+            let s = s.split_whitespace().next()?;
+            match s.split_once('/') {
+                Some((n, d)) => Some(n.parse::<f64>().ok()? / d.parse::<f64>().ok()?),
+                None => s.parse().ok(),
+            }
+        }
+        _ => None,
+    }
+    .filter(|n| n.is_finite())
+}
+
+fn number_value(value: &Value) -> Option<u32> {
+    fraction(value).map(|n| n as u32)
+}
+
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn entry(tag: u16, values: &[i32]) -> MakerNoteEntry {
+    MakerNoteEntry {
+        tag,
+        // SLONG, which reads back as a SHORT for the tags that are one.
+        data_type: 9,
+        count: values.len() as u32,
+        value: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+    }
+}
+
+// "Red +2, Blue -5" from ExifTool, "40 -100" with -n.  Whole steps only go
+// up to 9, the raw values are multiples of 20.
+fn fine_tune(value: &Value) -> Option<MakerNoteEntry> {
+    let text = text(value)?;
+    let numbers: Vec<i32> = text
+        .split(|c: char| !(c.is_ascii_digit() || c == '-' || c == '+'))
+        .filter_map(|n| n.parse().ok())
+        .collect();
+    let [red, blue] = numbers[..] else {
+        return None;
+    };
+    let scale = if red.abs() <= 9 && blue.abs() <= 9 {
+        20
+    } else {
+        1
+    };
+    Some(entry(WHITE_BALANCE_FINE_TUNE, &[red * scale, blue * scale]))
+}
+
+fn maker_note_value(tag: u16, convert: Convert, printed: bool, value: &Value) -> Option<i32> {
+    match value {
+        Value::Number(n) if printed => convert(&n.to_string()).or_else(|| raw(n)),
+        Value::Number(n) => raw(n),
+        Value::String(s) => ALIASES
+            .iter()
+            .find(|(t, alias, _)| *t == tag && alias.eq_ignore_ascii_case(s))
+            .map(|(_, _, raw)| *raw)
+            .or_else(|| convert(s)),
+        _ => None,
+    }
+}
+
+fn raw(n: &serde_json::Number) -> Option<i32> {
+    n.as_i64().and_then(|n| i32::try_from(n).ok())
+}
+
+// Splits "MakerNotes:FilmMode" into its group and name.
+fn split_key(key: &str) -> (Option<&str>, &str) {
+    match key.split_once(':') {
+        Some((group, name)) => (Some(group), name),
+        None => (None, key),
+    }
+}
+
+fn record_settings(record: &Map<String, Value>) -> Result<FujifilmSettings, FilmError> {
+    let mut entries = vec![];
+    let mut shot_info = ShotInfo::default();
+    let mut body = Body::default();
+    let mut has_maker_note = false;
+
+    for (key, value) in record {
+        let (group, name) = split_key(key);
+
+        if group.is_none_or(|g| MAKER_NOTE_GROUPS.contains(&g)) {
+            has_maker_note |= group.is_some();
+            let tag = TAGS.iter().find(|(tag_name, ..)| *tag_name == name);
+            match (name, tag) {
+                ("WhiteBalanceFineTune", _) => entries.extend(fine_tune(value)),
+                // ExifTool has already masked the flag off the image count.
+                ("InternalSerialNumber", _) => body.internal_serial_number = text(value),
+                ("ImageCount", _) => body.image_count = number_value(value),
+                // ExifTool prints values it doesn't know, and some it does
+                // but we don't, e.g. "n/a" or "Film Simulation".  Only the
+                // film mode can't do without; an advanced filter we can't
+                // read is still a filter, and anything else is left out.
+                (_, Some((_, tag, convert, printed))) => {
+                    let raw = match maker_note_value(*tag, *convert, *printed, value) {
+                        Some(raw) => Some(raw),
+                        None if *tag == FILM_MODE => return Err(unexpected(value, name)),
+                        None if *tag == ADVANCED_FILTER => Some(UNKNOWN_ADVANCED_FILTER),
+                        None => None,
+                    };
+                    if let Some(raw) = raw {
+                        entries.push(entry(*tag, &[raw]));
+                    }
+                    has_maker_note = true;
+                }
+                _ => {}
+            }
+        }
+
+        if group.is_none_or(|g| EXIF_GROUPS.contains(&g)) {
+            match name {
+                "ExposureTime" => shot_info.exposure_time = fraction(value),
+                "FNumber" => shot_info.f_number = fraction(value),
+                "ISO" => shot_info.iso = number_value(value),
+                "ExposureCompensation" => shot_info.exposure_compensation = fraction(value),
+                "LensModel" => shot_info.lens_model = text(value),
+                "FocalLength" => shot_info.focal_length = fraction(value),
+                "FocalLengthIn35mmFormat" => shot_info.focal_length_35mm = number_value(value),
+                "Model" => body.model = text(value),
+                "SerialNumber" => body.serial_number = text(value),
+                _ => {}
+            }
+        }
+    }
+
+    if !has_maker_note {
+        return Err(FilmError::NotAFujifilmFile);
+    }
+
+    let mut settings = settings_from_entries(&entries)?;
+    settings.shot_info = (shot_info != ShotInfo::default()).then_some(shot_info);
+    settings.body = (body != Body::default()).then_some(body);
+    Ok(settings)
+}
+
+/// Reads the settings of every file in an `exiftool -j -G` dump.
+pub fn exiftool_settings(json: &str) -> Result<Vec<ExifToolRecord>, FilmError> {
+    let invalid = |message: String| FilmError::InvalidExifToolJson(message);
+    let records: Vec<Map<String, Value>> =
+        serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+
+    Ok(records
+        .iter()
+        .map(|record| ExifToolRecord {
+            source_file: record
+                .get("SourceFile")
+                .and_then(text)
+                .unwrap_or_default()
+                .into(),
+            settings: record_settings(record),
+        })
+        .collect())
+}
+
+// "MediumHard" -> "medium hard"
+fn words(id: &str) -> String {
+    let mut words = String::new();
+    for c in id.chars() {
+        if c.is_ascii_uppercase() && !words.is_empty() {
+            words.push(' ');
+        }
+        words.push(c.to_ascii_lowercase());
+    }
+    words
+}
+
+fn signed(n: f64) -> String {
+    if n > 0.0 {
+        format!("+{}", n)
+    } else {
+        n.to_string()
+    }
+}
+
+// "+1 (medium hard)"
+fn scale<T: Setting>(value: &T) -> String {
+    let step = value.step().unwrap_or_default();
+    format!("{} ({})", signed(step), words(&value.id()))
+}
+
+// The tones are described as if they were sharpness.  Half steps only get
+// the number.
+fn tone(value: &ToneCurve) -> Value {
+    match Sharpness::parse(&value.value().to_string()) {
+        Some(sharpness) if value.half_steps() % 2 == 0 => Value::from(format!(
+            "{} ({})",
+            signed(value.value()),
+            words(&sharpness.id())
+        )),
+        _ => Value::from(value.value()),
+    }
+}
+
+fn alias(tag: u16, raw: i32) -> Option<&'static str> {
+    ALIASES
+        .iter()
+        .find(|(t, _, r)| *t == tag && *r == raw)
+        .map(|(_, alias, _)| *alias)
+}
+
+fn printed<T: Setting<Raw = i32>>(tag: u16, value: &T) -> Value {
+    let alias = value.encode().and_then(|raw| alias(tag, raw));
+    Value::from(alias.map(str::to_string).unwrap_or_else(|| value.label()))
+}
+
+// "-2/3", "+1/2", "+1"
+fn ev(n: f64) -> String {
+    let sign = if n > 0.0 { "+" } else { "-" };
+    if n.fract() == 0.0 {
+        signed(n)
+    } else if (n * 2.0).fract() == 0.0 {
+        format!("{}{}/2", sign, (n * 2.0).abs())
+    } else {
+        format!("{}{}/3", sign, (n * 3.0).round().abs())
+    }
+}
+
+/// The settings under the names and in the format `exiftool -j -G` uses, so
+/// tools built around ExifTool can read them.
+pub fn exiftool_json(settings: &FujifilmSettings) -> Map<String, Value> {
+    let mut json = Map::new();
+    let mut maker_note = |name: &str, value: Value| {
+        json.insert(format!("{}:{}", MAKER_NOTE_GROUPS[0], name), value);
+    };
+
+    maker_note("Sharpness", Value::from(scale(&settings.sharpness)));
+    maker_note("WhiteBalance", printed(0x1002, &settings.white_balance));
+    let saturation = settings.saturation_tag().and_then(|raw| alias(0x1003, raw));
+    match (saturation, settings.color) {
+        (Some(monochrome), _) => maker_note("Saturation", Value::from(monochrome)),
+        (None, Some(color)) => maker_note("Saturation", Value::from(scale(&color))),
+        _ => {}
+    }
+    if let Some(kelvin) = settings.color_temperature {
+        maker_note("ColorTemperature", Value::from(kelvin));
+    }
+    let fine_tune = settings.white_balance_fine_tune;
+    maker_note(
+        "WhiteBalanceFineTune",
+        Value::from(format!(
+            "Red {:+}, Blue {:+}",
+            fine_tune.red(),
+            fine_tune.blue()
+        )),
+    );
+    maker_note(
+        "HighISONoiseReduction",
+        Value::from(scale(&settings.noise_reduction)),
+    );
+    maker_note("Clarity", Value::from(settings.clarity.steps()));
+    maker_note("ShadowTone", tone(&settings.shadow));
+    maker_note("HighlightTone", tone(&settings.highlight));
+    maker_note(
+        "GrainEffectRoughness",
+        Value::from(settings.grain_roughness.label()),
+    );
+    maker_note("GrainEffectSize", Value::from(settings.grain_size.label()));
+    maker_note(
+        "ColorChromeEffect",
+        Value::from(settings.color_chrome.label()),
+    );
+    maker_note(
+        "ColorChromeFXBlue",
+        Value::from(settings.color_chrome_fx_blue.label()),
+    );
+    if let Some(sequence) = settings.sequence {
+        maker_note("AutoBracketing", printed(0x1100, &sequence.auto_bracketing));
+        maker_note("SequenceNumber", Value::from(sequence.number));
+    }
+    if !settings.advanced_filter.is_off() {
+        maker_note("AdvancedFilter", printed(0x1201, &settings.advanced_filter));
+    }
+    if !settings.film_mode.is_monochrome() {
+        maker_note("FilmMode", printed(0x1401, &settings.film_mode));
+    }
+
+    let auto = settings.dynamic_range == DynamicRange::Auto;
+    maker_note(
+        "DynamicRangeSetting",
+        Value::from(if auto { "Auto" } else { "Manual" }),
+    );
+    let development = settings
+        .applied_dynamic_range
+        .unwrap_or(settings.dynamic_range);
+    if let Some(raw) = development.encode().filter(|raw| *raw != 0) {
+        maker_note("DevelopmentDynamicRange", Value::from(raw));
+        if auto {
+            maker_note("AutoDynamicRange", Value::from(format!("{}%", raw)));
+        }
+    }
+
+    if let Some(body) = &settings.body {
+        if let Some(serial) = &body.internal_serial_number {
+            maker_note("InternalSerialNumber", Value::from(serial.as_str()));
+        }
+        if let Some(count) = body.image_count {
+            maker_note("ImageCount", Value::from(count));
+        }
+    }
+
+    let mut exif = |name: &str, value: Value| {
+        json.insert(format!("{}:{}", EXIF_GROUPS[0], name), value);
+    };
+
+    if let Some(body) = &settings.body {
+        if let Some(model) = &body.model {
+            exif("Model", Value::from(model.as_str()));
+        }
+        if let Some(serial) = &body.serial_number {
+            exif("SerialNumber", Value::from(serial.as_str()));
+        }
+    }
+
+    if let Some(info) = &settings.shot_info {
+        if let Some(time) = info.exposure_time {
+            exif(
+                "ExposureTime",
+                if time > 0.0 && time < 1.0 {
+                    Value::from(format!("1/{}", (1.0 / time).round()))
+                } else {
+                    Value::from(time)
+                },
+            );
+        }
+        if let Some(f_number) = info.f_number {
+            exif("FNumber", Value::from((f_number * 10.0).round() / 10.0));
+        }
+        if let Some(iso) = info.iso {
+            exif("ISO", Value::from(iso));
+        }
+        if let Some(compensation) = info.exposure_compensation {
+            exif("ExposureCompensation", Value::from(ev(compensation)));
+        }
+        if let Some(lens) = &info.lens_model {
+            exif("LensModel", Value::from(lens.as_str()));
+        }
+        if let Some(mm) = info.focal_length {
+            exif("FocalLength", Value::from(format!("{:.1} mm", mm)));
+        }
+        if let Some(mm) = info.focal_length_35mm {
+            exif("FocalLengthIn35mmFormat", Value::from(format!("{} mm", mm)));
+        }
+    }
+
+    json
+}
+
+/// Reads an `exiftool -j -G` dump from a file.
+pub fn read_exiftool_json(path: &Path) -> Result<Vec<ExifToolRecord>, FilmError> {
+    exiftool_settings(&std::fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MonochromeFilter, WhiteBalanceFineTune};
+
+    const DUMP: &str = r#"[{
+        "SourceFile": "DSCF0001.JPG",
+        "EXIF:Model": "X-T3",
+        "EXIF:ExposureTime": "1/250",
+        "EXIF:FNumber": 2.8,
+        "EXIF:ISO": 400,
+        "EXIF:ExposureCompensation": "-2/3",
+        "EXIF:FocalLength": "23.0 mm",
+        "MakerNotes:Sharpness": "+1 (medium hard)",
+        "MakerNotes:WhiteBalance": "Kelvin",
+        "MakerNotes:ColorTemperature": 5500,
+        "MakerNotes:WhiteBalanceFineTune": "Red +2, Blue -5",
+        "MakerNotes:Saturation": "+2 (high)",
+        "MakerNotes:HighISONoiseReduction": "-4 (weakest)",
+        "MakerNotes:Clarity": -2,
+        "MakerNotes:ShadowTone": "+1 (medium hard)",
+        "MakerNotes:HighlightTone": -0.5,
+        "MakerNotes:GrainEffectRoughness": "Weak",
+        "MakerNotes:GrainEffectSize": "Small",
+        "MakerNotes:ColorChromeEffect": "Strong",
+        "MakerNotes:ColorChromeFXBlue": "Off",
+        "MakerNotes:FilmMode": "Classic Chrome",
+        "MakerNotes:DynamicRangeSetting": "Auto",
+        "MakerNotes:DevelopmentDynamicRange": 200,
+        "MakerNotes:ImageCount": 1234
+    }, {
+        "SourceFile": "DSCF0002.JPG",
+        "MakerNotes:Saturation": "Acros Yellow Filter",
+        "MakerNotes:WhiteBalanceFineTune": "40 -100",
+        "MakerNotes:ShadowTone": -16
+    }, {
+        "SourceFile": "IMG_0001.JPG",
+        "EXIF:Model": "iPhone"
+    }]"#;
+
+    #[test]
+    fn reads_exiftool_json() {
+        let records = exiftool_settings(DUMP).unwrap();
+        assert_eq!(records[0].source_file, PathBuf::from("DSCF0001.JPG"));
+
+        let settings = records[0].settings.as_ref().unwrap();
+        assert_eq!(settings.film_mode, FilmMode::ClassicChrome);
+        assert_eq!(settings.sharpness, Sharpness::MediumHard);
+        assert_eq!(settings.white_balance, WhiteBalance::Kelvin);
+        assert_eq!(settings.color_temperature, Some(5500));
+        assert_eq!(
+            settings.white_balance_fine_tune,
+            WhiteBalanceFineTune::new(2, -5).unwrap()
+        );
+        assert_eq!(settings.color, Some(Saturation::High));
+        assert_eq!(settings.noise_reduction, NoiseReduction::Weakest);
+        assert_eq!(settings.clarity, Clarity::from_steps(-2).unwrap());
+        assert_eq!(settings.shadow, ToneCurve::from_half_steps(2).unwrap());
+        assert_eq!(settings.highlight, ToneCurve::from_half_steps(-1).unwrap());
+        assert_eq!(settings.grain_roughness, GrainRoughness::Weak);
+        assert_eq!(settings.color_chrome, ColorChrome::Strong);
+        assert_eq!(settings.dynamic_range, DynamicRange::Auto);
+        assert_eq!(settings.applied_dynamic_range, Some(DynamicRange::DR200));
+
+        let shot_info = settings.shot_info.as_ref().unwrap();
+        assert_eq!(shot_info.iso, Some(400));
+        assert_eq!(shot_info.focal_length, Some(23.0));
+        let body = settings.body.as_ref().unwrap();
+        assert_eq!(body.model.as_deref(), Some("X-T3"));
+        assert_eq!(body.image_count, Some(1234));
+
+        // Raw values from -n dumps.
+        let acros = records[1].settings.as_ref().unwrap();
+        assert_eq!(acros.film_mode, FilmMode::Acros);
+        assert_eq!(acros.monochrome_filter, MonochromeFilter::Yellow);
+        assert_eq!(
+            acros.white_balance_fine_tune,
+            settings.white_balance_fine_tune
+        );
+        assert_eq!(acros.shadow, settings.shadow);
+
+        assert!(matches!(
+            records[2].settings,
+            Err(FilmError::NotAFujifilmFile)
+        ));
+        assert!(exiftool_settings("{}").is_err());
+    }
+
+    #[test]
+    fn skips_values_we_dont_know() {
+        let record = |film_mode: &str, advanced_filter: &str| {
+            let json = format!(
+                r#"[{{
+                    "MakerNotes:FilmMode": "{}",
+                    "MakerNotes:Sharpness": "Film Simulation",
+                    "MakerNotes:Saturation": "n/a",
+                    "MakerNotes:WhiteBalance": "Underwater",
+                    "MakerNotes:WhiteBalanceFineTune": "n/a",
+                    "MakerNotes:AdvancedFilter": "{}"
+                }}]"#,
+                film_mode, advanced_filter
+            );
+            exiftool_settings(&json).unwrap().remove(0).settings
+        };
+
+        let settings = record("Bleach Bypass", "Off").unwrap();
+        assert_eq!(settings.film_mode, FilmMode::EternaBleachBypass);
+        assert_eq!(settings.sharpness, Sharpness::Normal);
+        assert_eq!(settings.advanced_filter, AdvancedFilter::Off);
+
+        let settings = record("Velvia", "Unknown (0x100000)").unwrap();
+        assert_eq!(settings.advanced_filter, AdvancedFilter::Unknown);
+
+        assert!(matches!(
+            record("Unknown (0xf00)", "Off"),
+            Err(FilmError::UnexpectedValue(_))
+        ));
+    }
+
+    #[test]
+    fn writes_exiftool_json() {
+        let records = exiftool_settings(DUMP).unwrap();
+        for record in &records[..2] {
+            let settings = record.settings.as_ref().unwrap();
+            let json = exiftool_json(settings);
+            assert_eq!(record_settings(&json).unwrap(), *settings);
+        }
+
+        let json = exiftool_json(records[0].settings.as_ref().unwrap());
+        assert_eq!(json["MakerNotes:Sharpness"], "+1 (medium hard)");
+        assert_eq!(json["MakerNotes:ShadowTone"], "+1 (medium hard)");
+        assert_eq!(json["MakerNotes:HighlightTone"], -0.5);
+        assert_eq!(json["MakerNotes:FilmMode"], "Classic Chrome");
+        assert_eq!(json["MakerNotes:AutoDynamicRange"], "200%");
+        assert_eq!(json["EXIF:ExposureCompensation"], "-2/3");
+        assert_eq!(json["EXIF:FocalLength"], "23.0 mm");
+
+        let json = exiftool_json(records[1].settings.as_ref().unwrap());
+        assert_eq!(json["MakerNotes:Saturation"], "Acros Yellow Filter");
+        assert!(!json.contains_key("MakerNotes:FilmMode"));
+    }
+}
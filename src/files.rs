@@ -0,0 +1,65 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Finding photos on disk, shared by the catalog scan and the watch mode.
+
+use crate::FilmError;
+use std::path::{Path, PathBuf};
+
+// Files a directory scan picks up.  Files named explicitly are always
+// scanned.
+const PHOTO_EXTENSIONS: &[&str] = &["jpg", "jpeg", "mpo", "tif", "tiff", "hif", "heif"];
+
+pub(crate) fn is_photo(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| PHOTO_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+// A directory, and not a symlink to one, so that a link back up the tree
+// can't send a walk round in circles.
+pub(crate) fn is_real_dir(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|m| m.is_dir())
+}
+
+// Every photo under `path`, or `path` itself when it is a file.  Only an error
+// for `path` itself is returned; subdirectories that can't be read go into
+// `failed` and the walk carries on.
+pub(crate) fn collect_files(
+    path: &Path,
+    files: &mut Vec<PathBuf>,
+    failed: &mut Vec<(PathBuf, FilmError)>,
+) -> Result<(), FilmError> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(path)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for entry in entries {
+        if is_real_dir(&entry) {
+            if let Err(e) = collect_files(&entry, files, failed) {
+                failed.push((entry, e));
+            }
+        } else if is_photo(&entry) {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,221 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Builds minimal synthetic JPEGs so that the tests don't need real camera
+// files.  The result is an SOI marker, a single APP1 segment holding a
+// little-endian TIFF structure with IFD0 -> Exif IFD -> MakerNote, and an EOI
+// marker.  There is no image data.  Without the EXIF reader only the
+// MakerNotes are built, for the tests of the no_std core.
+
+use crate::*;
+
+const SHORT: u16 = 3;
+#[cfg(feature = "exif")]
+const ASCII: u16 = 2;
+#[cfg(feature = "exif")]
+const LONG: u16 = 4;
+#[cfg(feature = "exif")]
+const RATIONAL: u16 = 5;
+const UNDEFINED: u16 = 7;
+const SLONG: u16 = 9;
+#[cfg(feature = "exif")]
+const SRATIONAL: u16 = 10;
+
+pub fn entry(tag: u16, data_type: u16, count: u32, value: Vec<u8>) -> MakerNoteEntry {
+    MakerNoteEntry {
+        tag,
+        data_type,
+        count,
+        value,
+    }
+}
+
+pub fn short(tag: u16, n: u16) -> MakerNoteEntry {
+    entry(tag, SHORT, 1, n.to_le_bytes().to_vec())
+}
+
+pub fn slong(tag: u16, n: i32) -> MakerNoteEntry {
+    entry(tag, SLONG, 1, n.to_le_bytes().to_vec())
+}
+
+#[cfg(feature = "exif")]
+pub fn rational(tag: u16, numerator: u32, denominator: u32) -> MakerNoteEntry {
+    let mut value = numerator.to_le_bytes().to_vec();
+    value.extend_from_slice(&denominator.to_le_bytes());
+    entry(tag, RATIONAL, 1, value)
+}
+
+#[cfg(feature = "exif")]
+pub fn srational(tag: u16, numerator: i32, denominator: i32) -> MakerNoteEntry {
+    let mut value = numerator.to_le_bytes().to_vec();
+    value.extend_from_slice(&denominator.to_le_bytes());
+    entry(tag, SRATIONAL, 1, value)
+}
+
+// Serializes an IFD that starts at `base` within the enclosing TIFF structure
+// or MakerNote.  Values that don't fit in four bytes are placed right after
+// the IFD.
+fn ifd(entries: &[MakerNoteEntry], base: usize) -> Vec<u8> {
+    let mut out = (entries.len() as u16).to_le_bytes().to_vec();
+    let mut data = Vec::new();
+    let data_start = base + 2 + 12 * entries.len() + 4;
+
+    for e in entries {
+        out.extend_from_slice(&e.tag.to_le_bytes());
+        out.extend_from_slice(&e.data_type.to_le_bytes());
+        out.extend_from_slice(&e.count.to_le_bytes());
+        if e.value.len() <= 4 {
+            let mut inline = e.value.clone();
+            inline.resize(4, 0);
+            out.extend_from_slice(&inline);
+        } else {
+            out.extend_from_slice(&((data_start + data.len()) as u32).to_le_bytes());
+            data.extend_from_slice(&e.value);
+        }
+    }
+
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&data);
+    out
+}
+
+pub fn maker_note(entries: &[MakerNoteEntry]) -> Vec<u8> {
+    let mut out = b"FUJIFILM".to_vec();
+    out.extend_from_slice(&12u32.to_le_bytes());
+    out.extend_from_slice(&ifd(entries, 12));
+    out
+}
+
+#[cfg(feature = "exif")]
+pub fn jpeg_with_maker_note(maker_note: &[u8]) -> Vec<u8> {
+    jpeg_with_exif(&[entry(
+        crate::container::MAKER_NOTES_TAG,
+        UNDEFINED,
+        maker_note.len() as u32,
+        maker_note.to_vec(),
+    )])
+}
+
+#[cfg(feature = "exif")]
+pub fn ascii(tag: u16, s: &str) -> MakerNoteEntry {
+    let mut value = s.as_bytes().to_vec();
+    value.push(0);
+    entry(tag, ASCII, value.len() as u32, value)
+}
+
+#[cfg(feature = "exif")]
+// `exif_entries` go into the Exif IFD.
+pub fn jpeg_with_exif(exif_entries: &[MakerNoteEntry]) -> Vec<u8> {
+    let make = b"FUJIFILM\0".to_vec();
+    let ifd0_len = 2 + 12 * 2 + 4;
+    let exif_ifd_offset = 8 + ifd0_len + make.len();
+
+    let ifd0 = ifd(
+        &[
+            entry(0x010f, ASCII, make.len() as u32, make),
+            entry(
+                0x8769,
+                LONG,
+                1,
+                (exif_ifd_offset as u32).to_le_bytes().to_vec(),
+            ),
+        ],
+        8,
+    );
+    let exif_ifd = ifd(exif_entries, exif_ifd_offset);
+
+    let mut tiff = b"II*\0".to_vec();
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+    tiff.extend_from_slice(&ifd0);
+    tiff.extend_from_slice(&exif_ifd);
+
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend_from_slice(&tiff);
+
+    let mut out = vec![0xff, 0xd8, 0xff, 0xe1];
+    out.extend_from_slice(&((app1.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(&app1);
+    out.extend_from_slice(&[0xff, 0xd9]);
+    out
+}
+
+#[cfg(feature = "exif")]
+pub fn jpeg(settings: &FujifilmSettings) -> Vec<u8> {
+    jpeg_with_maker_note(&maker_note(&settings_entries(settings)))
+}
+
+// The inverse of the decoding in `settings_from_entries`.
+pub fn settings_entries(settings: &FujifilmSettings) -> Vec<MakerNoteEntry> {
+    let short_setting = |tag, raw: Option<i32>| raw.map(|raw| short(tag, raw as u16));
+    let slong_setting = |tag, raw: Option<i32>| raw.map(|raw| slong(tag, raw));
+
+    let mut entries = vec![entry(0x0000, UNDEFINED, 4, b"0130".to_vec())];
+
+    let (red, blue) = settings.white_balance_fine_tune.encode().unwrap();
+    let mut fine_tune = red.to_le_bytes().to_vec();
+    fine_tune.extend_from_slice(&blue.to_le_bytes());
+
+    entries.extend(
+        [
+            short_setting(0x1001, settings.sharpness.encode()),
+            short_setting(0x1002, settings.white_balance.encode()),
+            settings
+                .color_temperature
+                .map(|kelvin| short(0x1005, kelvin)),
+            short_setting(0x1003, settings.saturation_tag()),
+            Some(entry(0x100a, SLONG, 2, fine_tune)),
+            short_setting(0x100e, settings.noise_reduction.encode()),
+            slong_setting(0x100f, settings.clarity.encode()),
+            slong_setting(0x1040, settings.shadow.encode()),
+            slong_setting(0x1041, settings.highlight.encode()),
+            slong_setting(0x1047, settings.grain_roughness.encode()),
+            slong_setting(0x1048, settings.color_chrome.encode()),
+            short_setting(0x104c, settings.grain_size.encode()),
+            slong_setting(0x104e, settings.color_chrome_fx_blue.encode()),
+            settings
+                .sequence
+                .and_then(|s| short_setting(0x1100, s.auto_bracketing.encode())),
+            settings.sequence.map(|s| short(0x1101, s.number)),
+            slong_setting(
+                0x1201,
+                settings
+                    .advanced_filter
+                    .encode()
+                    .filter(|_| !settings.advanced_filter.is_off()),
+            ),
+            // Monochrome shots don't carry a film mode tag at all.
+            short_setting(0x1401, settings.film_mode.encode()),
+            Some(short(
+                0x1402,
+                match settings.dynamic_range {
+                    DynamicRange::Auto => DYNAMIC_RANGE_AUTO as u16,
+                    _ => 1,
+                },
+            )),
+            short_setting(
+                0x1403,
+                settings
+                    .applied_dynamic_range
+                    .unwrap_or(settings.dynamic_range)
+                    .encode(),
+            ),
+        ]
+        .into_iter()
+        .flatten(),
+    );
+
+    entries
+}
@@ -0,0 +1,503 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// X RAW STUDIO and the in-camera RAW conversion save their settings as
+// conversion profiles (.FP1, .FP2 and .FP3, newer versions adding elements).
+// A profile is a small XML file with one element per setting:
+//
+// <ConversionProfile application="XRFC" version="1.12.0.0">
+//   <PropertyGroup device="X-T3" label="Street">
+//     <FilmSimulation>Classic</FilmSimulation>
+//     <HighlightTone>-1</HighlightTone>
+//     ...
+//   </PropertyGroup>
+// </ConversionProfile>
+//
+// Elements we don't know, like the image size or color space, are ignored on
+// the way in and left for X RAW STUDIO to fill in on the way out.
+
+use crate::jpeg::write_atomically;
+use crate::{
+    Clarity, ColorChrome, ColorChromeFxBlue, DynamicRange, FilmError, FilmMode, FujifilmSettings,
+    GrainRoughness, GrainSize, MonochromeFilter, NoiseReduction, Recipe, Saturation, Setting,
+    Sharpness, ToneCurve, WhiteBalance, WhiteBalanceFineTune,
+};
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::path::Path;
+
+const APPLICATION: &str = "XRFC";
+const VERSION: &str = "1.12.0.0";
+
+// Monochrome simulations and their filters are a single value in a profile.
+const FILM_SIMULATIONS: &[(&str, FilmMode, MonochromeFilter)] = &[
+    ("Provia", FilmMode::Provia, MonochromeFilter::None),
+    ("Velvia", FilmMode::Velvia, MonochromeFilter::None),
+    ("Astia", FilmMode::Astia, MonochromeFilter::None),
+    ("PRONegHi", FilmMode::ProNegHi, MonochromeFilter::None),
+    ("PRONegStd", FilmMode::ProNegStd, MonochromeFilter::None),
+    ("Classic", FilmMode::ClassicChrome, MonochromeFilter::None),
+    ("Eterna", FilmMode::Eterna, MonochromeFilter::None),
+    (
+        "BleachBypass",
+        FilmMode::EternaBleachBypass,
+        MonochromeFilter::None,
+    ),
+    (
+        "ClassicNEG",
+        FilmMode::ClassicNegative,
+        MonochromeFilter::None,
+    ),
+    (
+        "NostalgicNeg",
+        FilmMode::NostalgicNeg,
+        MonochromeFilter::None,
+    ),
+    ("RealaAce", FilmMode::RealaACE, MonochromeFilter::None),
+    ("BW", FilmMode::Monochrome, MonochromeFilter::None),
+    ("BYe", FilmMode::Monochrome, MonochromeFilter::Yellow),
+    ("BR", FilmMode::Monochrome, MonochromeFilter::Red),
+    ("BG", FilmMode::Monochrome, MonochromeFilter::Green),
+    ("Acros", FilmMode::Acros, MonochromeFilter::None),
+    ("AcrosYe", FilmMode::Acros, MonochromeFilter::Yellow),
+    ("AcrosR", FilmMode::Acros, MonochromeFilter::Red),
+    ("AcrosG", FilmMode::Acros, MonochromeFilter::Green),
+    ("Sepia", FilmMode::Sepia, MonochromeFilter::None),
+];
+
+const WHITE_BALANCES: &[(&str, WhiteBalance)] = &[
+    ("Auto", WhiteBalance::Auto),
+    ("AutoWhite", WhiteBalance::AutoWhitePriority),
+    ("AutoAmbience", WhiteBalance::AutoAmbiancePriority),
+    ("Daylight", WhiteBalance::Daylight),
+    ("Shade", WhiteBalance::Cloudy),
+    ("Fluorescent1", WhiteBalance::DaylightFluorescent),
+    ("Fluorescent2", WhiteBalance::DayWhiteFluorescent),
+    ("Fluorescent3", WhiteBalance::WhiteFluorescent),
+    ("Incandescent", WhiteBalance::Incandescent),
+    ("Underwater", WhiteBalance::Underwater),
+    ("Temperature", WhiteBalance::Kelvin),
+    ("Custom1", WhiteBalance::Custom),
+    ("Custom2", WhiteBalance::Custom2),
+    ("Custom3", WhiteBalance::Custom3),
+];
+
+// Keeps whatever each RAF was shot with, which no recipe can describe.
+const AS_SHOT: &str = "AsShot";
+
+fn invalid(message: String) -> FilmError {
+    FilmError::InvalidProfile(message)
+}
+
+fn strength(value: &str) -> Option<i32> {
+    match value.to_ascii_uppercase().as_str() {
+        "OFF" => Some(0),
+        "WEAK" => Some(32),
+        "STRONG" => Some(64),
+        _ => None,
+    }
+}
+
+fn strength_name(raw: Option<i32>) -> &'static str {
+    match raw {
+        Some(64) => "STRONG",
+        Some(32) => "WEAK",
+        _ => "OFF",
+    }
+}
+
+type Properties = HashMap<String, String>;
+
+// The text of every element in the PropertyGroup, and the group's attributes.
+fn properties(xml: &str) -> Result<(Properties, Properties), FilmError> {
+    let xml_error = |e: quick_xml::Error| invalid(e.to_string());
+    let mut reader = Reader::from_str(xml);
+    let mut attributes = HashMap::new();
+    let mut properties = HashMap::new();
+    let mut in_group = false;
+    let mut found = false;
+    let mut current = None;
+
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(e) if e.name().as_ref() == b"PropertyGroup" => {
+                in_group = true;
+                found = true;
+                for attribute in e.attributes().flatten() {
+                    let key = String::from_utf8_lossy(attribute.key.as_ref()).to_string();
+                    let value = attribute.unescape_value().map_err(xml_error)?;
+                    attributes.insert(key, value.to_string());
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"PropertyGroup" => in_group = false,
+            Event::Start(e) if in_group => {
+                current = Some(String::from_utf8_lossy(e.name().as_ref()).to_string());
+            }
+            Event::End(_) => current = None,
+            Event::Text(t) => {
+                if let Some(name) = &current {
+                    let text = t.unescape().map_err(xml_error)?;
+                    properties.insert(name.clone(), text.trim().to_string());
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if !found {
+        return Err(invalid("no PropertyGroup element".to_string()));
+    }
+    Ok((properties, attributes))
+}
+
+/// Reads the settings in a conversion profile, and its label if it has one.
+pub fn parse_profile(xml: &str) -> Result<(Option<String>, FujifilmSettings), FilmError> {
+    let (properties, attributes) = properties(xml)?;
+    let get = |name: &str| properties.get(name).map(String::as_str);
+    let unknown = |name: &str, value: &str| invalid(format!("unknown {} value {}", name, value));
+
+    // Every setting a profile leaves out stays at the camera's default.
+    fn setting<T: Setting>(value: Option<&str>, name: &str) -> Result<Option<T>, FilmError> {
+        value
+            .map(|v| T::parse(v).ok_or_else(|| invalid(format!("unknown {} value {}", name, v))))
+            .transpose()
+    }
+
+    let mut settings = FujifilmSettings::new();
+
+    if let Some(value) = get("FilmSimulation") {
+        let (_, film_mode, filter) = FILM_SIMULATIONS
+            .iter()
+            .find(|(name, _, _)| name.eq_ignore_ascii_case(value))
+            .ok_or_else(|| unknown("FilmSimulation", value))?;
+        settings.film_mode = *film_mode;
+        settings.monochrome_filter = *filter;
+    }
+
+    if let Some(value) = get("WhiteBalance") {
+        if value.eq_ignore_ascii_case(AS_SHOT) {
+            return Err(invalid(
+                "WhiteBalance AsShot keeps each RAF's own white balance".to_string(),
+            ));
+        }
+        settings.white_balance = WHITE_BALANCES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(value))
+            .map(|(_, white_balance)| *white_balance)
+            .ok_or_else(|| unknown("WhiteBalance", value))?;
+    }
+    if settings.white_balance == WhiteBalance::Kelvin {
+        settings.color_temperature = get("WBColorTemp").and_then(|v| v.parse().ok());
+    }
+
+    let shift = |name| -> Result<i8, FilmError> {
+        get(name)
+            .map(|v| v.parse().map_err(|_| unknown(name, v)))
+            .unwrap_or(Ok(0))
+    };
+    let (red, blue) = (shift("WBShiftR")?, shift("WBShiftB")?);
+    settings.white_balance_fine_tune = WhiteBalanceFineTune::new(red, blue)
+        .ok_or_else(|| invalid(format!("white balance shift R:{} B:{}", red, blue)))?;
+
+    if let Some(value) = get("DynamicRange") {
+        settings.dynamic_range = match value.to_ascii_uppercase().as_str() {
+            "AUTO" | "0" => DynamicRange::Auto,
+            _ => DynamicRange::parse(&format!("DR{}", value))
+                .ok_or_else(|| unknown("DynamicRange", value))?,
+        };
+    }
+
+    if let Some(tone) = setting::<ToneCurve>(get("HighlightTone"), "HighlightTone")? {
+        settings.highlight = tone;
+    }
+    if let Some(tone) = setting::<ToneCurve>(get("ShadowTone"), "ShadowTone")? {
+        settings.shadow = tone;
+    }
+    if let Some(color) = setting::<Saturation>(get("Color"), "Color")? {
+        settings.color = Some(color);
+    }
+    if settings.film_mode.is_monochrome() {
+        settings.color = None;
+    }
+    if let Some(sharpness) = setting::<Sharpness>(get("Sharpness"), "Sharpness")? {
+        settings.sharpness = sharpness;
+    }
+    // X RAW STUDIO spells it without the e.
+    let noise_reduction = get("NoisReduction").or(get("NoiseReduction"));
+    if let Some(nr) = setting::<NoiseReduction>(noise_reduction, "NoisReduction")? {
+        settings.noise_reduction = nr;
+    }
+    if let Some(clarity) = setting::<Clarity>(get("Clarity"), "Clarity")? {
+        settings.clarity = clarity;
+    }
+
+    let effect = |name| -> Result<Option<i32>, FilmError> {
+        get(name)
+            .map(|v| strength(v).ok_or_else(|| unknown(name, v)))
+            .transpose()
+    };
+    if let Some(raw) = effect("GrainEffect")? {
+        settings.grain_roughness = GrainRoughness::decode(raw)?;
+        // FP1 profiles predate the grain size, which was always small.
+        settings.grain_size = match (raw, get("GrainEffectSize")) {
+            (0, _) => GrainSize::Off,
+            (_, Some(size)) if size.eq_ignore_ascii_case("LARGE") => GrainSize::Large,
+            (_, Some(size)) if !size.eq_ignore_ascii_case("SMALL") => {
+                return Err(unknown("GrainEffectSize", size))
+            }
+            _ => GrainSize::Small,
+        };
+    }
+    if let Some(raw) = effect("ChromeEffect")? {
+        settings.color_chrome = ColorChrome::decode(raw)?;
+    }
+    if let Some(raw) = effect("ColorChromeBlue")? {
+        settings.color_chrome_fx_blue = ColorChromeFxBlue::decode(raw)?;
+    }
+
+    let label = attributes.get("label").filter(|l| !l.is_empty()).cloned();
+    Ok((label, settings))
+}
+
+/// Reads a conversion profile as a recipe.  It is named after the profile's
+/// label, or the file when the profile has none.
+pub fn read_profile(path: &Path) -> Result<Recipe, FilmError> {
+    let (label, settings) = parse_profile(&std::fs::read_to_string(path)?)?;
+    let name = label.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    Ok(Recipe { name, settings })
+}
+
+/// Writes the settings as an FP3 conversion profile.  The profile is made for
+/// the camera that took the photo when we know which one it was; X RAW STUDIO
+/// only offers profiles to RAFs from the same model.
+pub fn profile(settings: &FujifilmSettings, label: &str) -> Result<String, FilmError> {
+    let unsupported =
+        |what: String| invalid(format!("{} can't be written to a conversion profile", what));
+
+    if !settings.advanced_filter.is_off() {
+        return Err(unsupported(format!(
+            "The {} filter",
+            settings.advanced_filter
+        )));
+    }
+
+    let film_simulation = FILM_SIMULATIONS
+        .iter()
+        .find(|(_, film_mode, filter)| {
+            *film_mode == settings.film_mode && *filter == settings.monochrome_filter
+        })
+        .map(|(name, _, _)| *name)
+        .ok_or_else(|| unsupported(format!("Film simulation {}", settings.film_mode)))?;
+    let white_balance = WHITE_BALANCES
+        .iter()
+        .find(|(_, white_balance)| *white_balance == settings.white_balance)
+        .map(|(name, _)| *name)
+        .ok_or_else(|| unsupported(format!("{} white balance", settings.white_balance)))?;
+
+    // The dynamic range the JPEG was developed with, even if Auto picked it.
+    let dynamic_range = match settings
+        .applied_dynamic_range
+        .unwrap_or(settings.dynamic_range)
+    {
+        DynamicRange::Auto => "Auto".to_string(),
+        dynamic_range => dynamic_range.encode().unwrap_or_default().to_string(),
+    };
+    let step = |step: Option<f64>| step.unwrap_or_default().to_string();
+
+    let mut properties = vec![
+        ("FilmSimulation", film_simulation.to_string()),
+        ("DynamicRange", dynamic_range),
+        ("WhiteBalance", white_balance.to_string()),
+        (
+            "WBShiftR",
+            settings.white_balance_fine_tune.red().to_string(),
+        ),
+        (
+            "WBShiftB",
+            settings.white_balance_fine_tune.blue().to_string(),
+        ),
+    ];
+    if let (WhiteBalance::Kelvin, Some(kelvin)) =
+        (settings.white_balance, settings.color_temperature)
+    {
+        properties.push(("WBColorTemp", kelvin.to_string()));
+    }
+    properties.extend([
+        ("HighlightTone", step(settings.highlight.step())),
+        ("ShadowTone", step(settings.shadow.step())),
+    ]);
+    if let Some(color) = settings.color {
+        properties.push(("Color", step(color.step())));
+    }
+    properties.extend([
+        ("Sharpness", step(settings.sharpness.step())),
+        ("NoisReduction", step(settings.noise_reduction.step())),
+        ("Clarity", step(settings.clarity.step())),
+        (
+            "GrainEffect",
+            strength_name(settings.grain_roughness.encode()).to_string(),
+        ),
+        (
+            "GrainEffectSize",
+            match settings.grain_size {
+                GrainSize::Large => "LARGE",
+                _ => "SMALL",
+            }
+            .to_string(),
+        ),
+        (
+            "ChromeEffect",
+            strength_name(settings.color_chrome.encode()).to_string(),
+        ),
+        (
+            "ColorChromeBlue",
+            strength_name(settings.color_chrome_fx_blue.encode()).to_string(),
+        ),
+    ]);
+
+    let device = settings
+        .body
+        .as_ref()
+        .and_then(|b| b.model.as_deref())
+        .map(|model| format!(" device=\"{}\"", escape(model)))
+        .unwrap_or_default();
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <ConversionProfile application=\"{}\" version=\"{}\">\n  \
+         <PropertyGroup{} label=\"{}\">\n",
+        APPLICATION,
+        VERSION,
+        device,
+        escape(label)
+    );
+    for (name, value) in properties {
+        xml.push_str(&format!("    <{0}>{1}</{0}>\n", name, escape(&value)));
+    }
+    xml.push_str("  </PropertyGroup>\n</ConversionProfile>\n");
+    Ok(xml)
+}
+
+/// Writes the settings to a conversion profile at `path`, replacing it
+/// atomically.
+pub fn write_profile(
+    path: &Path,
+    settings: &FujifilmSettings,
+    label: &str,
+) -> Result<(), FilmError> {
+    write_atomically(path, profile(settings, label)?.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AdvancedFilter;
+
+    const STREET: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<ConversionProfile application="XRFC" version="1.12.0.0">
+  <PropertyGroup device="X-T3" version="X-T3_0100" label="Street">
+    <SerialNumber>12345678</SerialNumber>
+    <ImageSize>L3x2</ImageSize>
+    <FilmSimulation>Classic</FilmSimulation>
+    <DynamicRange>200</DynamicRange>
+    <WhiteBalance>Temperature</WhiteBalance>
+    <WBShiftR>2</WBShiftR>
+    <WBShiftB>-5</WBShiftB>
+    <WBColorTemp>5500</WBColorTemp>
+    <HighlightTone>-1</HighlightTone>
+    <ShadowTone>1.5</ShadowTone>
+    <Color>2</Color>
+    <Sharpness>-1</Sharpness>
+    <NoisReduction>-4</NoisReduction>
+    <GrainEffect>WEAK</GrainEffect>
+    <ChromeEffect>STRONG</ChromeEffect>
+    <ColorSpace>sRGB</ColorSpace>
+  </PropertyGroup>
+</ConversionProfile>
+"#;
+
+    #[test]
+    fn reads_profile() {
+        let (label, settings) = parse_profile(STREET).unwrap();
+
+        assert_eq!(label.as_deref(), Some("Street"));
+        assert_eq!(settings.film_mode, FilmMode::ClassicChrome);
+        assert_eq!(settings.dynamic_range, DynamicRange::DR200);
+        assert_eq!(settings.white_balance, WhiteBalance::Kelvin);
+        assert_eq!(settings.color_temperature, Some(5500));
+        assert_eq!(
+            settings.white_balance_fine_tune,
+            WhiteBalanceFineTune::new(2, -5).unwrap()
+        );
+        assert_eq!(settings.highlight, ToneCurve::from_half_steps(-2).unwrap());
+        assert_eq!(settings.shadow, ToneCurve::from_half_steps(3).unwrap());
+        assert_eq!(settings.color, Some(Saturation::High));
+        assert_eq!(settings.sharpness, Sharpness::MediumSoft);
+        assert_eq!(settings.noise_reduction, NoiseReduction::Weakest);
+        // An FP1 has no grain size.
+        assert_eq!(settings.grain_roughness, GrainRoughness::Weak);
+        assert_eq!(settings.grain_size, GrainSize::Small);
+        assert_eq!(settings.color_chrome, ColorChrome::Strong);
+        assert_eq!(settings.color_chrome_fx_blue, ColorChromeFxBlue::Off);
+
+        let unknown = STREET.replace("Classic<", "Kodachrome<");
+        assert!(matches!(
+            parse_profile(&unknown),
+            Err(FilmError::InvalidProfile(_))
+        ));
+
+        let as_shot = STREET.replace(">Temperature<", ">AsShot<");
+        assert!(matches!(
+            parse_profile(&as_shot),
+            Err(FilmError::InvalidProfile(_))
+        ));
+    }
+
+    #[test]
+    fn round_trips_profile() {
+        let (_, street) = parse_profile(STREET).unwrap();
+        let mut acros = FujifilmSettings::new();
+        acros.film_mode = FilmMode::Acros;
+        acros.monochrome_filter = MonochromeFilter::Red;
+        acros.color = None;
+        acros.grain_roughness = GrainRoughness::Strong;
+        acros.grain_size = GrainSize::Large;
+        acros.clarity = Clarity::from_steps(-3).unwrap();
+
+        let mut bleach_bypass = FujifilmSettings::new();
+        bleach_bypass.film_mode = FilmMode::EternaBleachBypass;
+
+        for settings in [street, acros, bleach_bypass] {
+            let xml = profile(&settings, "Test").unwrap();
+            assert_eq!(
+                parse_profile(&xml).unwrap(),
+                (Some("Test".to_string()), settings)
+            );
+        }
+
+        let mut filtered = FujifilmSettings::new();
+        filtered.film_mode = FilmMode::Provia;
+        filtered.advanced_filter = AdvancedFilter::ToyCamera;
+        assert!(profile(&filtered, "Toy").is_err());
+    }
+}
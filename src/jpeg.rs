@@ -0,0 +1,394 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Just enough JPEG to find and replace metadata segments.  We only look at the
+// segments before the start of scan; the scan itself, and every segment we
+// don't touch, is copied byte for byte.
+
+#[cfg(feature = "xmp")]
+use crate::xmp;
+use crate::FilmError;
+#[cfg(feature = "xmp")]
+use std::io::Write;
+use std::io::{BufRead, Read, Seek};
+#[cfg(feature = "xmp")]
+use std::path::{Path, PathBuf};
+
+const SOI: u8 = 0xd8;
+const EOI: u8 = 0xd9;
+const SOS: u8 = 0xda;
+#[cfg(feature = "xmp")]
+const APP0: u8 = 0xe0;
+const APP1: u8 = 0xe1;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+#[cfg(feature = "xmp")]
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+// A segment's length field is two bytes and includes itself.
+#[cfg(feature = "xmp")]
+const MAX_SEGMENT_DATA: usize = 0xffff - 2;
+
+pub struct Segment<'a> {
+    pub marker: u8,
+    pub data: &'a [u8],
+}
+
+/// A JPEG split into its metadata segments and everything from the start of
+/// scan onwards.
+pub struct Jpeg<'a> {
+    pub segments: Vec<Segment<'a>>,
+    // Only needed to write the file back.
+    #[cfg(feature = "xmp")]
+    pub rest: &'a [u8],
+}
+
+fn invalid(message: &str) -> FilmError {
+    FilmError::InvalidJpeg(message.to_string())
+}
+
+impl<'a> Jpeg<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, FilmError> {
+        if data.get(0..2) != Some(&[0xff, SOI]) {
+            return Err(invalid("Missing start of image marker."));
+        }
+
+        let mut segments = vec![];
+        let mut offset = 2;
+
+        loop {
+            if data.get(offset) != Some(&0xff) {
+                return Err(invalid("Expected a marker."));
+            }
+            // Markers may be preceded by any number of fill bytes.
+            while data.get(offset + 1) == Some(&0xff) {
+                offset += 1;
+            }
+            let marker = *data.get(offset + 1).ok_or_else(|| invalid("Truncated."))?;
+
+            if marker == SOS || marker == EOI {
+                return Ok(Jpeg {
+                    segments,
+                    #[cfg(feature = "xmp")]
+                    rest: &data[offset..],
+                });
+            }
+
+            let length = data
+                .get(offset + 2..offset + 4)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+                .ok_or_else(|| invalid("Truncated."))?;
+            let segment = data
+                .get(offset + 4..offset + 2 + length)
+                .filter(|_| length >= 2)
+                .ok_or_else(|| invalid("Truncated segment."))?;
+
+            segments.push(Segment {
+                marker,
+                data: segment,
+            });
+            offset += 2 + length;
+        }
+    }
+
+    #[cfg(feature = "xmp")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![0xff, SOI];
+        for segment in &self.segments {
+            out.extend_from_slice(&[0xff, segment.marker]);
+            out.extend_from_slice(&((segment.data.len() + 2) as u16).to_be_bytes());
+            out.extend_from_slice(segment.data);
+        }
+        out.extend_from_slice(self.rest);
+        out
+    }
+
+    #[cfg(feature = "xmp")]
+    fn xmp_index(&self) -> Option<usize> {
+        self.segments
+            .iter()
+            .position(|s| s.marker == APP1 && s.data.starts_with(XMP_HEADER))
+    }
+
+    /// The embedded XMP packet, if there is one.
+    #[cfg(feature = "xmp")]
+    pub fn xmp(&self) -> Option<String> {
+        let segment = &self.segments[self.xmp_index()?];
+        Some(String::from_utf8_lossy(&segment.data[XMP_HEADER.len()..]).into_owned())
+    }
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, FilmError> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Reads the TIFF structure out of the EXIF segment, skipping every other
+/// segment without reading it and stopping at the start of scan.  Only the
+/// few bytes of each marker and the EXIF segment itself are read, which
+/// matters for large files on network mounts.
+///
+/// `None` when the file isn't a JPEG, with the reader back at the start.
+pub(crate) fn read_exif_segment<R: BufRead + Seek>(
+    reader: &mut R,
+) -> Result<Option<Vec<u8>>, FilmError> {
+    let mut soi = [0; 2];
+    if reader.read_exact(&mut soi).is_err() || soi != [0xff, SOI] {
+        reader.rewind()?;
+        return Ok(None);
+    }
+
+    loop {
+        if read_u8(reader)? != 0xff {
+            return Err(invalid("Expected a marker."));
+        }
+        let mut marker = read_u8(reader)?;
+        while marker == 0xff {
+            marker = read_u8(reader)?;
+        }
+
+        if marker == SOS || marker == EOI {
+            return Err(FilmError::NotAFujifilmFile);
+        }
+
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length) as usize;
+        if length < 2 {
+            return Err(invalid("Truncated segment."));
+        }
+
+        if marker == APP1 {
+            let mut data = vec![0; length - 2];
+            reader.read_exact(&mut data)?;
+            if data.starts_with(EXIF_HEADER) {
+                data.drain(..EXIF_HEADER.len());
+                return Ok(Some(data));
+            }
+        } else {
+            reader.seek_relative(length as i64 - 2)?;
+        }
+    }
+}
+
+/// What `embed_keywords` did, or would do in a dry run.
+#[cfg(feature = "xmp")]
+pub struct EmbedResult {
+    pub path: PathBuf,
+    // Keywords that weren't in the file yet.
+    pub added: Vec<String>,
+    // Keywords an earlier run added that no longer apply.  Nothing is written
+    // when this and `added` are both empty.
+    pub removed: Vec<String>,
+}
+
+/// Adds `keywords` to the dc:subject of the JPEG's own XMP packet, creating
+/// the packet if needed, and takes out the ones an earlier run added that
+/// `keywords` no longer has.  The EXIF segment and the image data are left
+/// untouched.  The file is replaced atomically: we write a temporary file in
+/// the same directory and rename it over the original.
+#[cfg(feature = "xmp")]
+pub fn embed_keywords(
+    path: &Path,
+    keywords: &[String],
+    dry_run: bool,
+) -> Result<EmbedResult, FilmError> {
+    let data = std::fs::read(path)?;
+    let mut jpeg = Jpeg::parse(&data)?;
+
+    let packet = jpeg.xmp().unwrap_or_else(xmp::new_packet);
+    let existing = xmp::subject_keywords(&packet)?;
+    let added: Vec<String> = keywords
+        .iter()
+        .filter(|k| !existing.contains(k))
+        .cloned()
+        .collect();
+
+    let result = EmbedResult {
+        path: path.to_path_buf(),
+        added,
+        removed: xmp::stale_keywords(&packet, keywords)?,
+    };
+
+    if dry_run || (result.added.is_empty() && result.removed.is_empty()) {
+        return Ok(result);
+    }
+
+    let mut segment = XMP_HEADER.to_vec();
+    segment.extend_from_slice(xmp::merge_keywords(&packet, keywords)?.as_bytes());
+    if segment.len() > MAX_SEGMENT_DATA {
+        return Err(FilmError::Xmp(
+            "XMP packet doesn't fit in a JPEG segment.".to_string(),
+        ));
+    }
+
+    let new_segment = Segment {
+        marker: APP1,
+        data: &segment,
+    };
+    match jpeg.xmp_index() {
+        Some(index) => jpeg.segments[index] = new_segment,
+        None => {
+            // The XMP packet goes right after the JFIF and EXIF segments.
+            let index = jpeg
+                .segments
+                .iter()
+                .take_while(|s| {
+                    s.marker == APP0 || (s.marker == APP1 && s.data.starts_with(EXIF_HEADER))
+                })
+                .count();
+            jpeg.segments.insert(index, new_segment);
+        }
+    }
+
+    write_atomically(path, &jpeg.to_bytes())?;
+    Ok(result)
+}
+
+// Writes a temporary file next to `path` and renames it over `path`, keeping
+// the permissions of the file it replaces.
+#[cfg(feature = "xmp")]
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> Result<(), FilmError> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".film-detect.tmp");
+    let tmp = path.with_file_name(name);
+
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        match std::fs::metadata(path) {
+            Ok(metadata) => std::fs::set_permissions(&tmp, metadata.permissions())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        std::fs::rename(&tmp, path)
+    };
+
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        e.into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[cfg(feature = "xmp")]
+    fn temp_copy(name: &str, data: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("film-detect-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[cfg(feature = "xmp")]
+    fn keywords() -> Vec<String> {
+        vec!["Classic Chrome".to_string(), "Street Chrome".to_string()]
+    }
+
+    #[test]
+    #[cfg(feature = "xmp")]
+    fn round_trips_segments() {
+        let data = fixtures::jpeg(&crate::FujifilmSettings::new());
+        let jpeg = Jpeg::parse(&data).unwrap();
+
+        assert_eq!(jpeg.segments.len(), 1);
+        assert_eq!(jpeg.to_bytes(), data);
+    }
+
+    #[test]
+    fn reads_only_the_exif_segment() {
+        let settings = crate::FujifilmSettings::new();
+        // A JFIF APP0 segment first.
+        let mut data = vec![0xff, SOI, 0xff, 0xe0, 0x00, 0x06];
+        data.extend_from_slice(b"JFIF");
+        data.extend_from_slice(&fixtures::jpeg(&settings)[2..]);
+        let exif_end = data.len() - 2;
+        data.extend(std::iter::repeat_n(0x55, 1 << 20));
+
+        let mut reader = std::io::Cursor::new(data);
+        let tiff = read_exif_segment(&mut reader).unwrap().unwrap();
+        assert!(tiff.starts_with(b"II*\0"));
+        // Nothing past the EXIF segment was read.
+        assert!(reader.position() as usize <= exif_end);
+
+        let exif = exif::Reader::new().read_raw(tiff).unwrap();
+        assert_eq!(
+            crate::container::settings_from_exif(&exif).unwrap(),
+            settings
+        );
+
+        let mut tiff = std::io::Cursor::new(b"II*\0\x08\0\0\0".to_vec());
+        assert!(read_exif_segment(&mut tiff).unwrap().is_none());
+        assert_eq!(tiff.position(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "xmp")]
+    fn embeds_keywords() {
+        let original = fixtures::jpeg(&crate::FujifilmSettings::new());
+        let path = temp_copy("embed.jpg", &original);
+
+        let result = embed_keywords(&path, &keywords(), false).unwrap();
+        assert_eq!(result.added, keywords());
+
+        let data = std::fs::read(&path).unwrap();
+        let jpeg = Jpeg::parse(&data).unwrap();
+        let original = Jpeg::parse(&original).unwrap();
+
+        // The EXIF segment and the scan are untouched.
+        assert_eq!(jpeg.segments[0].data, original.segments[0].data);
+        assert_eq!(jpeg.rest, original.rest);
+        assert_eq!(
+            xmp::subject_keywords(&jpeg.xmp().unwrap()).unwrap(),
+            keywords()
+        );
+
+        // A second run finds nothing to do.
+        let result = embed_keywords(&path, &keywords(), false).unwrap();
+        assert!(result.added.is_empty());
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+
+        // Keywords we added that no longer apply are taken out.
+        let result = embed_keywords(&path, &keywords()[..1], false).unwrap();
+        assert_eq!(result.removed, keywords()[1..]);
+        let jpeg = std::fs::read(&path).unwrap();
+        let jpeg = Jpeg::parse(&jpeg).unwrap();
+        assert_eq!(
+            xmp::subject_keywords(&jpeg.xmp().unwrap()).unwrap(),
+            keywords()[..1]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "xmp")]
+    fn dry_run_leaves_file_alone() {
+        let original = fixtures::jpeg(&crate::FujifilmSettings::new());
+        let path = temp_copy("dry-run.jpg", &original);
+
+        let result = embed_keywords(&path, &keywords(), true).unwrap();
+        assert_eq!(result.added, keywords());
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -14,12 +14,100 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use serde::{Deserialize, Serialize, Serializer};
-use std::io;
+// The MakerNote decoder and the settings types only need `alloc`; everything
+// that touches files is behind the `std` feature and the ones that build on it.
+#![cfg_attr(not(feature = "std"), no_std)]
 
-const MAKER_NOTES_TAG: u16 = 37500;
+extern crate alloc;
 
-#[derive(Deserialize, Debug)]
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[macro_use]
+mod setting;
+
+mod body;
+#[cfg(feature = "capi")]
+mod capi;
+#[cfg(feature = "catalog")]
+mod catalog;
+#[cfg(feature = "exif")]
+mod container;
+#[cfg(all(feature = "std", feature = "serde"))]
+mod exiftool;
+#[cfg(any(feature = "catalog", feature = "watch"))]
+mod files;
+#[cfg(test)]
+mod fixtures;
+#[cfg(feature = "xmp")]
+mod fp;
+#[cfg(feature = "exif")]
+mod jpeg;
+mod makernote;
+#[cfg(feature = "exif")]
+mod mpo;
+#[cfg(feature = "exif")]
+mod organize;
+#[cfg(feature = "python")]
+mod python;
+mod recipe;
+mod sequence;
+#[cfg(feature = "server")]
+mod serve;
+mod shot;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "watch")]
+mod watch;
+#[cfg(feature = "xmp")]
+mod xmp;
+
+pub use body::{identifies_body, redact_entries, Body, Redaction};
+#[cfg(feature = "catalog")]
+pub use catalog::{Catalog, CatalogEntry, ScanSummary};
+#[cfg(feature = "exif")]
+pub use container::{
+    get_fujifilm_settings, get_fujifilm_settings_from_bytes, get_maker_note_entries,
+};
+#[cfg(all(feature = "std", feature = "serde"))]
+pub use exiftool::{exiftool_json, exiftool_settings, read_exiftool_json, ExifToolRecord};
+#[cfg(feature = "xmp")]
+pub use fp::{parse_profile, profile, read_profile, write_profile};
+#[cfg(feature = "xmp")]
+pub use jpeg::{embed_keywords, EmbedResult};
+pub use makernote::MakerNoteEntry;
+#[cfg(feature = "exif")]
+pub use mpo::{get_embedded_settings, EmbeddedImage};
+#[cfg(feature = "exif")]
+pub use organize::{
+    organize, render_template, Collision, OrganizeOptions, Placement, TransferMode,
+    DEFAULT_TEMPLATE,
+};
+pub use recipe::find_recipe;
+#[cfg(all(feature = "std", feature = "serde"))]
+pub use recipe::load_recipes;
+pub use recipe::Recipe;
+#[cfg(feature = "std")]
+pub use sequence::{group_sequences, Group, GroupKind};
+pub use sequence::{AutoBracketing, Sequence};
+#[cfg(feature = "server")]
+pub use serve::serve;
+pub use setting::{Choice, Choices, Setting};
+pub use shot::ShotInfo;
+#[cfg(feature = "watch")]
+pub use watch::{WatchEvent, Watcher};
+#[cfg(feature = "xmp")]
+pub use xmp::{keywords, update_xmp, write_xmp_sidecar};
+
+// Tag 0x1402 is 0 for Auto and 1 for a dynamic range picked by hand.
+const DYNAMIC_RANGE_AUTO: i32 = 0;
+
+/// The color setting of a color film simulation.
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Saturation {
     Normal,
     MediumHigh,
@@ -28,101 +116,114 @@ pub enum Saturation {
     High,
     MediumLow,
     Low,
-    NoneBW,
-    BWRed,
-    BWYellow,
-    BWGreen,
-    BWSepia,
     VeryLow,
     Lowest,
-    Acros,
-    AcrosRed,
-    AcrosYellow,
-    AcrosGreen,
-}
-
-impl Saturation {
-    fn from_u16(n: u16) -> Result<Self, FilmError> {
-        match n {
-            0x0 => Ok(Saturation::Normal),
-            0x80 => Ok(Saturation::MediumHigh),
-            0xc0 => Ok(Saturation::VeryHigh),
-            0xe0 => Ok(Saturation::Highest),
-            0x100 => Ok(Saturation::High),
-            0x180 => Ok(Saturation::MediumLow),
-            0x200 => Ok(Saturation::Low),
-            0x300 => Ok(Saturation::NoneBW),
-            0x301 => Ok(Saturation::BWRed),
-            0x302 => Ok(Saturation::BWYellow),
-            0x303 => Ok(Saturation::BWGreen),
-            0x310 => Ok(Saturation::BWSepia),
-            0x4c0 => Ok(Saturation::VeryLow),
-            0x4e0 => Ok(Saturation::Lowest),
-            0x500 => Ok(Saturation::Acros),
-            0x501 => Ok(Saturation::AcrosRed),
-            0x502 => Ok(Saturation::AcrosYellow),
-            0x503 => Ok(Saturation::AcrosGreen),
-            _ => Err(FilmError::UnexpectedValue(format!(
-                "Failed to parse {} as saturation value.",
-                n
-            ))),
-        }
-    }
 }
 
-#[derive(Deserialize, Debug)]
-pub enum Sharpness {
-    Softest = -4,  // -4  0x0
-    VerySoft = -3, // -3  0x1
-    Soft,          // -2  0x2
-    MediumSoft,    // -1  0x82
-    Normal = 0,    //  0  0x3
-    MediumHard,    // +1  0x84
-    Hard,          // +2  0x4
-    VeryHard,      // +3  0x5
-    Hardest,       // +4  0x6
-}
-
-impl Sharpness {
-    fn from_u16(n: u16) -> Result<Self, FilmError> {
-        match n {
-            0x0 => Ok(Self::Softest),
-            0x1 => Ok(Self::VerySoft),
-            0x2 => Ok(Self::Soft),
-            0x3 => Ok(Self::Normal),
-            0x4 => Ok(Self::Hard),
-            0x5 => Ok(Self::VeryHard),
-            0x6 => Ok(Self::Hardest),
-            0x82 => Ok(Self::MediumSoft),
-            0x84 => Ok(Self::MediumHard),
-            _ => Err(FilmError::UnexpectedValue(format!(
-                "Failed to parse {} as sharpness value.",
-                n
-            ))),
+impl Choices for Saturation {
+    const NAME: &'static str = "saturation";
+    const CHOICES: &'static [Choice<Self>] = &[
+        choice(Self::Lowest, 0x4e0, "Lowest", "-4", -4),
+        choice(Self::VeryLow, 0x4c0, "VeryLow", "-3", -3),
+        choice(Self::Low, 0x200, "Low", "-2", -2),
+        choice(Self::MediumLow, 0x180, "MediumLow", "-1", -1),
+        choice(Self::Normal, 0x0, "Normal", "0", 0),
+        choice(Self::MediumHigh, 0x80, "MediumHigh", "+1", 1),
+        choice(Self::High, 0x100, "High", "+2", 2),
+        choice(Self::VeryHigh, 0xc0, "VeryHigh", "+3", 3),
+        choice(Self::Highest, 0xe0, "Highest", "+4", 4),
+    ];
+}
+
+// The filter is the low nibble of the saturation tag.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum MonochromeFilter {
+    #[default]
+    None,
+    Yellow,
+    Red,
+    Green,
+}
+
+impl Choices for MonochromeFilter {
+    const NAME: &'static str = "monochrome filter";
+    const CHOICES: &'static [Choice<Self>] = &[
+        label(Self::None, Some(0x0), "None", "None"),
+        label(Self::Red, Some(0x1), "Red", "Red"),
+        label(Self::Yellow, Some(0x2), "Yellow", "Yellow"),
+        label(Self::Green, Some(0x3), "Green", "Green"),
+    ];
+}
+
+// Tag 0x1003 holds either the color setting of a color film simulation, or
+// the monochrome film simulation and its filter.
+enum SaturationTag {
+    Color(Saturation),
+    Monochrome(FilmMode, MonochromeFilter),
+}
+
+impl SaturationTag {
+    fn of(settings: &FujifilmSettings) -> Self {
+        match (settings.film_mode.is_monochrome(), settings.color) {
+            (false, Some(color)) => Self::Color(color),
+            (false, None) => Self::Color(Saturation::Normal),
+            (true, _) => Self::Monochrome(settings.film_mode, settings.monochrome_filter),
         }
     }
-}
 
-impl Serialize for Sharpness {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
+    fn decode(raw: i32) -> Result<Self, FilmError> {
+        let film_mode = match (raw & !0xf, raw & 0xf) {
+            (0x300, _) => FilmMode::Monochrome,
+            (0x500, _) => FilmMode::Acros,
+            (0x310, 0) => FilmMode::Sepia,
+            _ => return Saturation::decode(raw).map(Self::Color),
+        };
+        Ok(Self::Monochrome(
+            film_mode,
+            MonochromeFilter::decode(raw & 0xf)?,
+        ))
+    }
+
+    fn encode(&self) -> Option<i32> {
         match self {
-            Self::Softest => serializer.serialize_i32(-4),
-            Self::VerySoft => serializer.serialize_i32(-3),
-            Self::Soft => serializer.serialize_i32(-2),
-            Self::MediumSoft => serializer.serialize_i32(-1),
-            Self::Normal => serializer.serialize_i32(0),
-            Self::MediumHard => serializer.serialize_i32(1),
-            Self::Hard => serializer.serialize_i32(2),
-            Self::VeryHard => serializer.serialize_i32(3),
-            Self::Hardest => serializer.serialize_i32(4),
+            Self::Color(color) => color.encode(),
+            Self::Monochrome(FilmMode::Monochrome, filter) => Some(0x300 + filter.encode()?),
+            Self::Monochrome(FilmMode::Acros, filter) => Some(0x500 + filter.encode()?),
+            Self::Monochrome(FilmMode::Sepia, _) => Some(0x310),
+            Self::Monochrome(..) => None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Sharpness {
+    Softest,
+    VerySoft,
+    Soft,
+    MediumSoft,
+    Normal,
+    MediumHard,
+    Hard,
+    VeryHard,
+    Hardest,
+}
+
+impl Choices for Sharpness {
+    const NAME: &'static str = "sharpness";
+    const CHOICES: &'static [Choice<Self>] = &[
+        choice(Self::Softest, 0x0, "Softest", "-4", -4),
+        choice(Self::VerySoft, 0x1, "VerySoft", "-3", -3),
+        choice(Self::Soft, 0x2, "Soft", "-2", -2),
+        choice(Self::MediumSoft, 0x82, "MediumSoft", "-1", -1),
+        choice(Self::Normal, 0x3, "Normal", "0", 0),
+        choice(Self::MediumHard, 0x84, "MediumHard", "+1", 1),
+        choice(Self::Hard, 0x4, "Hard", "+2", 2),
+        choice(Self::VeryHard, 0x5, "VeryHard", "+3", 3),
+        choice(Self::Hardest, 0x6, "Hardest", "+4", 4),
+    ];
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum DynamicRange {
     Auto,
     DR100,
@@ -130,160 +231,277 @@ pub enum DynamicRange {
     DR400,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Choices for DynamicRange {
+    const NAME: &'static str = "dynamic range";
+    const CHOICES: &'static [Choice<Self>] = &[
+        label(Self::Auto, Some(0), "Auto", "Auto"),
+        label(Self::DR100, Some(100), "DR100", "DR100"),
+        label(Self::DR200, Some(200), "DR200", "DR200"),
+        label(Self::DR400, Some(400), "DR400", "DR400"),
+    ];
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum WhiteBalance {
-    Auto,                           // 0x0
-    AutoWhitePriority,              // 0x1
-    AutoAmbiancePriority,           // 0x2
-    Daylight,                       // 0x100
-    Cloudy,                         // 0x200
-    DaylightFluorescent,            // 0x300
-    DayWhiteFluorescent,            // 0x301
-    WhiteFluorescent,               // 0x302
-    WarmWhiteFluorescent,           // 0x303
-    LivingRoomWarmWhiteFluorescent, // 0x304
-    Incandescent,                   // 0x400
-    Flash,                          // 0x500
-    Underwater,                     // 0x600
-    Custom,                         // 0xf00
-    Custom2,                        // 0xf01
-    Custom3,                        // 0xf02
-    Custom4,                        // 0xf03
-    Custom5,                        // 0xf04
-    Kelvin,                         // 0xff0
-}
-
-impl WhiteBalance {
-    fn from_u16(n: u16) -> Result<Self, FilmError> {
-        match n {
-            0x0 => Ok(Self::Auto),
-            0x1 => Ok(Self::AutoWhitePriority),
-            0x2 => Ok(Self::AutoAmbiancePriority),
-            0x100 => Ok(Self::Daylight),
-            0x200 => Ok(Self::Cloudy),
-            0x300 => Ok(Self::DaylightFluorescent),
-            0x301 => Ok(Self::DayWhiteFluorescent),
-            0x302 => Ok(Self::WhiteFluorescent),
-            0x303 => Ok(Self::WarmWhiteFluorescent),
-            0x304 => Ok(Self::LivingRoomWarmWhiteFluorescent),
-            0x400 => Ok(Self::Incandescent),
-            0x500 => Ok(Self::Flash),
-            0x600 => Ok(Self::Underwater),
-            0xf00 => Ok(Self::Custom),
-            0xf01 => Ok(Self::Custom2),
-            0xf02 => Ok(Self::Custom3),
-            0xf03 => Ok(Self::Custom4),
-            0xf04 => Ok(Self::Custom5),
-            0xff0 => Ok(Self::Kelvin),
-            _ => Err(FilmError::UnexpectedValue(format!(
-                "Failed to parse {} as white balance value.",
-                n
-            ))),
-        }
-    }
+    Auto,
+    AutoWhitePriority,
+    AutoAmbiancePriority,
+    Daylight,
+    Cloudy,
+    DaylightFluorescent,
+    DayWhiteFluorescent,
+    WhiteFluorescent,
+    WarmWhiteFluorescent,
+    LivingRoomWarmWhiteFluorescent,
+    Incandescent,
+    Flash,
+    Underwater,
+    Custom,
+    Custom2,
+    Custom3,
+    Custom4,
+    Custom5,
+    Kelvin,
+}
+
+impl Choices for WhiteBalance {
+    const NAME: &'static str = "white balance";
+    const CHOICES: &'static [Choice<Self>] = &[
+        label(Self::Auto, Some(0x0), "Auto", "Auto"),
+        label(
+            Self::AutoWhitePriority,
+            Some(0x1),
+            "AutoWhitePriority",
+            "Auto White Priority",
+        ),
+        label(
+            Self::AutoAmbiancePriority,
+            Some(0x2),
+            "AutoAmbiancePriority",
+            "Auto Ambiance Priority",
+        ),
+        label(Self::Daylight, Some(0x100), "Daylight", "Daylight"),
+        label(Self::Cloudy, Some(0x200), "Cloudy", "Cloudy"),
+        label(
+            Self::DaylightFluorescent,
+            Some(0x300),
+            "DaylightFluorescent",
+            "Daylight Fluorescent",
+        ),
+        label(
+            Self::DayWhiteFluorescent,
+            Some(0x301),
+            "DayWhiteFluorescent",
+            "Day White Fluorescent",
+        ),
+        label(
+            Self::WhiteFluorescent,
+            Some(0x302),
+            "WhiteFluorescent",
+            "White Fluorescent",
+        ),
+        label(
+            Self::WarmWhiteFluorescent,
+            Some(0x303),
+            "WarmWhiteFluorescent",
+            "Warm White Fluorescent",
+        ),
+        label(
+            Self::LivingRoomWarmWhiteFluorescent,
+            Some(0x304),
+            "LivingRoomWarmWhiteFluorescent",
+            "Living Room Warm White Fluorescent",
+        ),
+        label(
+            Self::Incandescent,
+            Some(0x400),
+            "Incandescent",
+            "Incandescent",
+        ),
+        label(Self::Flash, Some(0x500), "Flash", "Flash"),
+        label(Self::Underwater, Some(0x600), "Underwater", "Underwater"),
+        label(Self::Custom, Some(0xf00), "Custom", "Custom"),
+        label(Self::Custom2, Some(0xf01), "Custom2", "Custom2"),
+        label(Self::Custom3, Some(0xf02), "Custom3", "Custom3"),
+        label(Self::Custom4, Some(0xf03), "Custom4", "Custom4"),
+        label(Self::Custom5, Some(0xf04), "Custom5", "Custom5"),
+        label(Self::Kelvin, Some(0xff0), "Kelvin", "Kelvin"),
+    ];
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum NoiseReduction {
-    Normal,       // 0 (normal) 0x0
-    Strong,       // +2 (strong) 0x100
-    MediumStrong, // +1 (medium strong) 0x180
-    VeryStrong,   // +3 (very strong) 0x1c0
-    Strongest,    // +4 (strongest) 0x1e0
-    Weak,         // -2 (weak) 0x200
-    MediumWeak,   // -1 (medium weak) 0x280
-    VeryWeak,     // -3 (very weak) 0x2c0
-    Weakest,      // -4 (weakest) 0x2e0
-}
-
-impl NoiseReduction {
-    fn from_u16(n: u16) -> Result<Self, FilmError> {
-        match n {
-            0x0 => Ok(Self::Normal),
-            0x100 => Ok(Self::Strong),
-            0x180 => Ok(Self::MediumStrong),
-            0x1c0 => Ok(Self::VeryStrong),
-            0x1e0 => Ok(Self::Strongest),
-            0x200 => Ok(Self::Weak),
-            0x280 => Ok(Self::MediumWeak),
-            0x2c0 => Ok(Self::VeryWeak),
-            0x2e0 => Ok(Self::Weakest),
-            _ => Err(FilmError::UnexpectedValue(format!(
-                "Failed to parse {} as noise reduction value.",
-                n
-            ))),
-        }
-    }
+    Normal,
+    Strong,
+    MediumStrong,
+    VeryStrong,
+    Strongest,
+    Weak,
+    MediumWeak,
+    VeryWeak,
+    Weakest,
 }
 
-impl Serialize for NoiseReduction {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            Self::Strongest => serializer.serialize_i32(4),
-            Self::VeryStrong => serializer.serialize_i32(3),
-            Self::Strong => serializer.serialize_i32(2),
-            Self::MediumStrong => serializer.serialize_i32(1),
-            Self::Normal => serializer.serialize_i32(0),
-            Self::MediumWeak => serializer.serialize_i32(-1),
-            Self::Weak => serializer.serialize_i32(-2),
-            Self::VeryWeak => serializer.serialize_i32(-3),
-            Self::Weakest => serializer.serialize_i32(-4),
-        }
-    }
+impl Choices for NoiseReduction {
+    const NAME: &'static str = "noise reduction";
+    const CHOICES: &'static [Choice<Self>] = &[
+        choice(Self::Weakest, 0x2e0, "Weakest", "-4", -4),
+        choice(Self::VeryWeak, 0x2c0, "VeryWeak", "-3", -3),
+        choice(Self::Weak, 0x200, "Weak", "-2", -2),
+        choice(Self::MediumWeak, 0x280, "MediumWeak", "-1", -1),
+        choice(Self::Normal, 0x0, "Normal", "0", 0),
+        choice(Self::MediumStrong, 0x180, "MediumStrong", "1", 1),
+        choice(Self::Strong, 0x100, "Strong", "2", 2),
+        choice(Self::VeryStrong, 0x1c0, "VeryStrong", "3", 3),
+        choice(Self::Strongest, 0x1e0, "Strongest", "4", 4),
+    ];
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The red and blue white balance shift, -9 to +9 each.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct WhiteBalanceFineTune {
     red: i8,
     blue: i8,
 }
 
 impl WhiteBalanceFineTune {
-    fn from_i32(red: i32, blue: i32) -> Self {
-        let red = red / 20;
-        let blue = blue / 20;
-        WhiteBalanceFineTune {
-            red: red as i8,
-            blue: blue as i8,
-        }
+    const MAX: i8 = 9;
+
+    // One step is 20 in the MakerNote.
+    const RAW_PER_STEP: i32 = 20;
+
+    pub fn new(red: i8, blue: i8) -> Option<Self> {
+        (red.abs() <= Self::MAX && blue.abs() <= Self::MAX)
+            .then_some(WhiteBalanceFineTune { red, blue })
+    }
+
+    pub fn red(&self) -> i8 {
+        self.red
+    }
+
+    pub fn blue(&self) -> i8 {
+        self.blue
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Setting for WhiteBalanceFineTune {
+    type Raw = (i32, i32);
+    const NAME: &'static str = "white balance fine tune";
+
+    // Values between steps are rounded to the nearest one and values past
+    // the ends are clamped, rather than failing the whole photo.
+    fn decode((red, blue): (i32, i32)) -> Result<Self, FilmError> {
+        let step = |n: i32| {
+            let max = i64::from(Self::MAX);
+            let half = i64::from(Self::RAW_PER_STEP / 2);
+            (i64::from(n) + half)
+                .div_euclid(i64::from(Self::RAW_PER_STEP))
+                .clamp(-max, max) as i8
+        };
+        Ok(WhiteBalanceFineTune {
+            red: step(red),
+            blue: step(blue),
+        })
+    }
+
+    fn encode(&self) -> Option<(i32, i32)> {
+        Some((
+            self.red as i32 * Self::RAW_PER_STEP,
+            self.blue as i32 * Self::RAW_PER_STEP,
+        ))
+    }
+
+    fn id(&self) -> String {
+        self.label()
+    }
+
+    fn label(&self) -> String {
+        format!("R:{} B:{}", self.red, self.blue)
+    }
+
+    fn step(&self) -> Option<f64> {
+        None
+    }
+
+    // "R:2 B:-5"
+    fn parse(s: &str) -> Option<Self> {
+        let (red, blue) = s.split_once(' ')?;
+        let value = |s: &str, prefix: &str| {
+            let (p, n) = s.split_once(':')?;
+            p.eq_ignore_ascii_case(prefix).then(|| n.parse().ok())?
+        };
+        Self::new(value(red, "R")?, value(blue, "B")?)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum GrainRoughness {
     Off,
     Weak,
     Strong,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Choices for GrainRoughness {
+    const NAME: &'static str = "grain roughness";
+    const CHOICES: &'static [Choice<Self>] = &[
+        label(Self::Off, Some(0), "Off", "Off"),
+        label(Self::Weak, Some(32), "Weak", "Weak"),
+        label(Self::Strong, Some(64), "Strong", "Strong"),
+    ];
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum GrainSize {
     Off,
     Small,
     Large,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Choices for GrainSize {
+    const NAME: &'static str = "grain size";
+    const CHOICES: &'static [Choice<Self>] = &[
+        label(Self::Off, Some(0), "Off", "Off"),
+        label(Self::Small, Some(16), "Small", "Small"),
+        label(Self::Large, Some(32), "Large", "Large"),
+    ];
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ColorChrome {
     Off,
     Weak,
     Strong,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Choices for ColorChrome {
+    const NAME: &'static str = "color chrome";
+    const CHOICES: &'static [Choice<Self>] = &[
+        label(Self::Off, Some(0), "Off", "Off"),
+        label(Self::Weak, Some(32), "Weak", "Weak"),
+        label(Self::Strong, Some(64), "Strong", "Strong"),
+    ];
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ColorChromeFxBlue {
     Off,
     Weak,
     Strong,
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
+impl Choices for ColorChromeFxBlue {
+    const NAME: &'static str = "color chrome fx blue";
+    const CHOICES: &'static [Choice<Self>] = &[
+        label(Self::Off, Some(0), "Off", "Off"),
+        label(Self::Weak, Some(32), "Weak", "Weak"),
+        label(Self::Strong, Some(64), "Strong", "Strong"),
+    ];
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum FilmMode {
-    // None represents the case when Acros is selected.  Fuji puts the "Acros"
-    // value in the saturation field.  We duplicate it here for convenience.
+    // The camera didn't record a film mode.
     None,
     Provia,
     Velvia,
@@ -293,119 +511,384 @@ pub enum FilmMode {
     ClassicChrome,
     Eterna,
     ClassicNegative,
+    EternaBleachBypass,
     NostalgicNeg,
     RealaACE,
+    // The monochrome simulations aren't written to the film mode tag.  Fuji
+    // puts them in the saturation field instead.
+    Monochrome,
     Acros,
+    Sepia,
 }
 
-#[derive(Deserialize, Debug)]
-pub enum Shadow {
-    Plus4,
-    Plus3,
-    Plus2,
-    Plus1,
-    Zero,
-    Minus1,
-    Minus2,
-}
-
-impl Shadow {
-    fn from_i32(n: i32) -> Result<Self, FilmError> {
-        match n {
-            -64 => Ok(Self::Plus4),
-            -48 => Ok(Self::Plus3),
-            -32 => Ok(Self::Plus2),
-            -16 => Ok(Self::Plus1),
-            0 => Ok(Self::Zero),
-            16 => Ok(Self::Minus1),
-            32 => Ok(Self::Minus2),
-            _ => Err(FilmError::UnexpectedValue(format!(
-                "Failed to parse {} as shadow value.",
-                n
-            ))),
-        }
+impl Choices for FilmMode {
+    const NAME: &'static str = "film mode";
+    const CHOICES: &'static [Choice<Self>] = &[
+        label(Self::None, None, "None", "None"),
+        label(Self::Provia, Some(0x0), "Provia", "Provia"),
+        label(Self::Velvia, Some(0x400), "Velvia", "Velvia"),
+        label(Self::Astia, Some(0x120), "Astia", "Astia"),
+        label(Self::ProNegStd, Some(0x500), "ProNegStd", "Pro Neg Std"),
+        label(Self::ProNegHi, Some(0x501), "ProNegHi", "Pro Neg Hi"),
+        label(
+            Self::ClassicChrome,
+            Some(0x600),
+            "ClassicChrome",
+            "Classic Chrome",
+        ),
+        label(Self::Eterna, Some(0x700), "Eterna", "Eterna"),
+        label(
+            Self::ClassicNegative,
+            Some(0x800),
+            "ClassicNegative",
+            "Classic Negative",
+        ),
+        label(
+            Self::EternaBleachBypass,
+            Some(0x900),
+            "EternaBleachBypass",
+            "Eterna Bleach Bypass",
+        ),
+        label(
+            Self::NostalgicNeg,
+            Some(0xa00),
+            "NostalgicNeg",
+            "Nostalgic Neg",
+        ),
+        label(Self::RealaACE, Some(0xb00), "RealaACE", "Reala ACE"),
+        label(Self::Monochrome, None, "Monochrome", "Monochrome"),
+        label(Self::Acros, None, "Acros", "Acros"),
+        label(Self::Sepia, None, "Sepia", "Sepia"),
+    ];
+}
+
+impl FilmMode {
+    pub fn is_monochrome(&self) -> bool {
+        matches!(self, Self::Monochrome | Self::Acros | Self::Sepia)
     }
 }
 
-impl Serialize for Shadow {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            Self::Plus4 => serializer.serialize_i8(4),
-            Self::Plus3 => serializer.serialize_i8(3),
-            Self::Plus2 => serializer.serialize_i8(2),
-            Self::Plus1 => serializer.serialize_i8(1),
-            Self::Zero => serializer.serialize_i8(0),
-            Self::Minus1 => serializer.serialize_i8(-1),
-            Self::Minus2 => serializer.serialize_i8(-2),
-        }
+/// An in-camera effect applied on top of the film simulation.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum AdvancedFilter {
+    #[default]
+    Off,
+    PopColor,
+    HighKey,
+    ToyCamera,
+    Miniature,
+    DynamicTone,
+    PartialColorRed,
+    PartialColorYellow,
+    PartialColorGreen,
+    PartialColorBlue,
+    PartialColorOrange,
+    PartialColorPurple,
+    SoftFocus,
+    LowKey,
+    /// A filter this version doesn't know, e.g. one added by newer firmware.
+    Unknown,
+}
+
+impl Choices for AdvancedFilter {
+    const NAME: &'static str = "advanced filter";
+    const CHOICES: &'static [Choice<Self>] = &[
+        label(Self::Off, Some(0x0), "Off", "Off"),
+        label(Self::PopColor, Some(0x10000), "PopColor", "Pop Color"),
+        label(Self::HighKey, Some(0x20000), "HighKey", "High-Key"),
+        label(Self::ToyCamera, Some(0x30000), "ToyCamera", "Toy Camera"),
+        label(Self::Miniature, Some(0x40000), "Miniature", "Miniature"),
+        label(
+            Self::DynamicTone,
+            Some(0x50000),
+            "DynamicTone",
+            "Dynamic Tone",
+        ),
+        label(
+            Self::PartialColorRed,
+            Some(0x60001),
+            "PartialColorRed",
+            "Partial Color (Red)",
+        ),
+        label(
+            Self::PartialColorYellow,
+            Some(0x60002),
+            "PartialColorYellow",
+            "Partial Color (Yellow)",
+        ),
+        label(
+            Self::PartialColorGreen,
+            Some(0x60003),
+            "PartialColorGreen",
+            "Partial Color (Green)",
+        ),
+        label(
+            Self::PartialColorBlue,
+            Some(0x60004),
+            "PartialColorBlue",
+            "Partial Color (Blue)",
+        ),
+        label(
+            Self::PartialColorOrange,
+            Some(0x60005),
+            "PartialColorOrange",
+            "Partial Color (Orange)",
+        ),
+        label(
+            Self::PartialColorPurple,
+            Some(0x60006),
+            "PartialColorPurple",
+            "Partial Color (Purple)",
+        ),
+        label(Self::SoftFocus, Some(0x70000), "SoftFocus", "Soft Focus"),
+        label(Self::LowKey, Some(0x90000), "LowKey", "Low-Key"),
+        label(Self::Unknown, None, "Unknown", "Unknown"),
+    ];
+}
+
+impl AdvancedFilter {
+    pub fn is_off(&self) -> bool {
+        *self == Self::Off
     }
 }
 
-#[derive(Deserialize, Debug)]
-pub enum Highlight {
-    Plus4,
-    Plus3,
-    Plus2,
-    Plus1,
-    Zero,
-    Minus1,
-    Minus2,
-}
-
-impl Highlight {
-    fn from_i32(n: i32) -> Result<Self, FilmError> {
-        match n {
-            -64 => Ok(Self::Plus4),
-            -48 => Ok(Self::Plus3),
-            -32 => Ok(Self::Plus2),
-            -16 => Ok(Self::Plus1),
-            0 => Ok(Self::Zero),
-            16 => Ok(Self::Minus1),
-            32 => Ok(Self::Minus2),
-            _ => Err(FilmError::UnexpectedValue(format!(
-                "Failed to parse {} as highlight value.",
-                n
-            ))),
+// Table rows for settings on a -4 to +4 scale, and for ones without a scale.
+const fn choice<T>(
+    value: T,
+    raw: i32,
+    id: &'static str,
+    label: &'static str,
+    step: i8,
+) -> Choice<T> {
+    Choice {
+        value,
+        raw: Some(raw),
+        id,
+        label,
+        step: Some(step),
+    }
+}
+
+const fn label<T>(value: T, raw: Option<i32>, id: &'static str, label: &'static str) -> Choice<T> {
+    Choice {
+        value,
+        raw,
+        id,
+        label,
+        step: None,
+    }
+}
+
+/// A highlight or shadow tone value.  The camera works in half steps from
+/// -2 to +4, and so do we: the value is stored as a number of half steps.
+///
+/// Displays with an explicit sign, e.g. `+1.5`.  JSON gets the plain number,
+/// an integer for whole steps.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct ToneCurve {
+    half_steps: i8,
+}
+
+impl ToneCurve {
+    const MIN_HALF_STEPS: i8 = -4;
+    const MAX_HALF_STEPS: i8 = 8;
+
+    // One step is -16 in the MakerNote, so a half step is -8.
+    const RAW_PER_HALF_STEP: i32 = -8;
+
+    pub fn from_half_steps(half_steps: i8) -> Option<Self> {
+        (Self::MIN_HALF_STEPS..=Self::MAX_HALF_STEPS)
+            .contains(&half_steps)
+            .then_some(ToneCurve { half_steps })
+    }
+
+    pub fn from_f64(value: f64) -> Option<Self> {
+        let half_steps = value * 2.0;
+        // `% 1.0` rather than `fract`, which needs std.
+        if half_steps % 1.0 != 0.0 || !(-127.0..=127.0).contains(&half_steps) {
+            return None;
         }
+        Self::from_half_steps(half_steps as i8)
+    }
+
+    pub fn half_steps(&self) -> i8 {
+        self.half_steps
+    }
+
+    pub fn value(&self) -> f64 {
+        self.half_steps as f64 / 2.0
     }
 }
 
-impl Serialize for Highlight {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            Self::Plus4 => serializer.serialize_i8(4),
-            Self::Plus3 => serializer.serialize_i8(3),
-            Self::Plus2 => serializer.serialize_i8(2),
-            Self::Plus1 => serializer.serialize_i8(1),
-            Self::Zero => serializer.serialize_i8(0),
-            Self::Minus1 => serializer.serialize_i8(-1),
-            Self::Minus2 => serializer.serialize_i8(-2),
+impl Setting for ToneCurve {
+    type Raw = i32;
+    const NAME: &'static str = "tone";
+
+    fn decode(raw: i32) -> Result<Self, FilmError> {
+        (raw % Self::RAW_PER_HALF_STEP == 0)
+            .then(|| i8::try_from(raw / Self::RAW_PER_HALF_STEP).ok())
+            .flatten()
+            .and_then(Self::from_half_steps)
+            .ok_or_else(|| setting::unexpected(raw, Self::NAME))
+    }
+
+    fn encode(&self) -> Option<i32> {
+        Some(self.half_steps as i32 * Self::RAW_PER_HALF_STEP)
+    }
+
+    fn id(&self) -> String {
+        self.value().to_string()
+    }
+
+    fn label(&self) -> String {
+        if self.half_steps > 0 {
+            format!("+{}", self.value())
+        } else {
+            self.value().to_string()
         }
     }
+
+    fn step(&self) -> Option<f64> {
+        Some(self.value())
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        s.parse().ok().and_then(Self::from_f64)
+    }
+}
+
+/// Clarity, -5 to +5.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Clarity {
+    steps: i8,
+}
+
+impl Clarity {
+    const MAX: i8 = 5;
+    const RAW_PER_STEP: i32 = 1000;
+
+    pub fn from_steps(steps: i8) -> Option<Self> {
+        (steps.abs() <= Self::MAX).then_some(Clarity { steps })
+    }
+
+    pub fn steps(&self) -> i8 {
+        self.steps
+    }
+}
+
+impl Setting for Clarity {
+    type Raw = i32;
+    const NAME: &'static str = "clarity";
+
+    fn decode(raw: i32) -> Result<Self, FilmError> {
+        (raw % Self::RAW_PER_STEP == 0)
+            .then(|| i8::try_from(raw / Self::RAW_PER_STEP).ok())
+            .flatten()
+            .and_then(Self::from_steps)
+            .ok_or_else(|| setting::unexpected(raw, Self::NAME))
+    }
+
+    fn encode(&self) -> Option<i32> {
+        Some(self.steps as i32 * Self::RAW_PER_STEP)
+    }
+
+    fn id(&self) -> String {
+        self.steps.to_string()
+    }
+
+    fn label(&self) -> String {
+        self.steps.to_string()
+    }
+
+    fn step(&self) -> Option<f64> {
+        Some(self.steps as f64)
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        s.parse().ok().and_then(Self::from_steps)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+setting_traits!(
+    Saturation,
+    MonochromeFilter,
+    Sharpness,
+    DynamicRange,
+    WhiteBalance,
+    NoiseReduction,
+    GrainRoughness,
+    GrainSize,
+    ColorChrome,
+    ColorChromeFxBlue,
+    FilmMode,
+    AdvancedFilter,
+    ToneCurve,
+    Clarity,
+);
+
+impl fmt::Display for WhiteBalanceFineTune {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FujifilmSettings {
     white_balance: WhiteBalance,
     white_balance_fine_tune: WhiteBalanceFineTune,
     sharpness: Sharpness,
     noise_reduction: NoiseReduction,
-    clarity: i32,
-    shadow: Shadow,
-    highlight: Highlight,
+    clarity: Clarity,
+    shadow: ToneCurve,
+    highlight: ToneCurve,
     grain_roughness: GrainRoughness,
     grain_size: GrainSize,
     color_chrome: ColorChrome,
     color_chrome_fx_blue: ColorChromeFxBlue,
     film_mode: FilmMode,
     dynamic_range: DynamicRange,
-    saturation: Saturation,
+    // What the camera picked when the dynamic range was set to Auto.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    applied_dynamic_range: Option<DynamicRange>,
+    // In Kelvin: the one that was dialed in, or the one the camera measured
+    // for the automatic modes.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    color_temperature: Option<u16>,
+    // Only color film simulations have a color setting.
+    color: Option<Saturation>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    monochrome_filter: MonochromeFilter,
+    // Replaces the look of the film simulation, so no recipe matches while
+    // one is active.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "AdvancedFilter::is_off")
+    )]
+    advanced_filter: AdvancedFilter,
+    // Not a setting, but what reviews want to see next to one.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    shot_info: Option<ShotInfo>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    body: Option<Body>,
+    // Set for the frames of a bracket or burst.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    sequence: Option<Sequence>,
 }
 
 impl FujifilmSettings {
@@ -413,214 +896,140 @@ impl FujifilmSettings {
         FujifilmSettings {
             white_balance: WhiteBalance::Auto,
             sharpness: Sharpness::Normal,
-            white_balance_fine_tune: WhiteBalanceFineTune { red: 0, blue: 0 },
+            white_balance_fine_tune: WhiteBalanceFineTune::default(),
             noise_reduction: NoiseReduction::Normal,
-            clarity: 0,
-            shadow: Shadow::Zero,
-            highlight: Highlight::Zero,
+            clarity: Clarity::default(),
+            shadow: ToneCurve::default(),
+            highlight: ToneCurve::default(),
             grain_roughness: GrainRoughness::Off,
             grain_size: GrainSize::Off,
             color_chrome: ColorChrome::Off,
             color_chrome_fx_blue: ColorChromeFxBlue::Off,
             film_mode: FilmMode::None,
             dynamic_range: DynamicRange::Auto,
-            saturation: Saturation::Normal,
+            applied_dynamic_range: None,
+            color_temperature: None,
+            color: Some(Saturation::Normal),
+            monochrome_filter: MonochromeFilter::None,
+            advanced_filter: AdvancedFilter::Off,
+            shot_info: None,
+            body: None,
+            sequence: None,
         }
     }
 }
+impl FujifilmSettings {
+    /// The settings as label/value pairs, in the order the camera menus list
+    /// them.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![("Film simulation", self.film_mode.to_string())];
+        if self.film_mode.is_monochrome() {
+            fields.push(("Monochrome Filter", self.monochrome_filter.to_string()));
+        }
+        if !self.advanced_filter.is_off() {
+            fields.push(("Advanced Filter", self.advanced_filter.to_string()));
+        }
+        fields.extend([
+            (
+                "Grain",
+                format!("{} {}", self.grain_size, self.grain_roughness),
+            ),
+            ("Color Chrome", self.color_chrome.to_string()),
+            (
+                "Color Chrome FX Blue",
+                self.color_chrome_fx_blue.to_string(),
+            ),
+            ("White Balance", self.white_balance()),
+            (
+                "White Balance Fine Tune",
+                self.white_balance_fine_tune.to_string(),
+            ),
+            ("Dynamic Range", self.dynamic_range()),
+            ("Shadow", self.shadow.to_string()),
+            ("Highlight", self.highlight.to_string()),
+        ]);
+        if let Some(color) = &self.color {
+            fields.push(("Color", color.to_string()));
+        }
+        fields.extend([
+            ("Sharpness", self.sharpness.to_string()),
+            ("Noise Reduction", self.noise_reduction.to_string()),
+            ("Clarity", self.clarity.to_string()),
+        ]);
+        fields
+    }
 
-impl std::fmt::Display for WhiteBalance {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Auto => write!(f, "Auto"),
-            Self::AutoWhitePriority => write!(f, "Auto White Priority"),
-            Self::AutoAmbiancePriority => write!(f, "Auto Ambiance Priority"),
-            Self::Daylight => write!(f, "Daylight"),
-            Self::Cloudy => write!(f, "Cloudy"),
-            Self::DaylightFluorescent => write!(f, "Daylight Fluorescent"),
-            Self::DayWhiteFluorescent => write!(f, "Day White Fluorescent"),
-            Self::WhiteFluorescent => write!(f, "White Fluorescent"),
-            Self::WarmWhiteFluorescent => write!(f, "Warm White Fluorescent"),
-            Self::LivingRoomWarmWhiteFluorescent => write!(f, "Living Room Warm White Fluorescent"),
-            Self::Incandescent => write!(f, "Incandescent"),
-            Self::Flash => write!(f, "Flash"),
-            Self::Underwater => write!(f, "Underwater"),
-            Self::Custom => write!(f, "Custom"),
-            Self::Custom2 => write!(f, "Custom2"),
-            Self::Custom3 => write!(f, "Custom3"),
-            Self::Custom4 => write!(f, "Custom4"),
-            Self::Custom5 => write!(f, "Custom5"),
-            Self::Kelvin => write!(f, "Kelvin"),
+    // "Auto (5200K applied)", "Kelvin (5500K)".  The presets record a
+    // temperature too, but only Auto picks one.
+    fn white_balance(&self) -> String {
+        match (self.white_balance, self.color_temperature) {
+            (WhiteBalance::Kelvin, Some(kelvin)) => format!("Kelvin ({}K)", kelvin),
+            (
+                white_balance @ (WhiteBalance::Auto
+                | WhiteBalance::AutoWhitePriority
+                | WhiteBalance::AutoAmbiancePriority),
+                Some(kelvin),
+            ) => format!("{} ({}K applied)", white_balance, kelvin),
+            (white_balance, _) => white_balance.to_string(),
         }
     }
-}
 
-impl std::fmt::Display for FilmMode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::None => write!(f, "None"),
-            Self::Acros => write!(f, "Acros"),
-            Self::Provia => write!(f, "Provia"),
-            Self::Velvia => write!(f, "Velvia"),
-            Self::Astia => write!(f, "Astia"),
-            Self::ProNegStd => write!(f, "Pro Neg Std"),
-            Self::ProNegHi => write!(f, "Pro Neg Hi"),
-            Self::ClassicChrome => write!(f, "Classic Chrome"),
-            Self::Eterna => write!(f, "Eterna"),
-            Self::ClassicNegative => write!(f, "Classic Negative"),
-            Self::NostalgicNeg => write!(f, "Nostalgic Neg"),
-            Self::RealaACE => write!(f, "Reala ACE"),
+    // "Auto (DR200 applied)"
+    fn dynamic_range(&self) -> String {
+        match self.applied_dynamic_range {
+            Some(applied) => format!("{} ({} applied)", self.dynamic_range, applied),
+            None => self.dynamic_range.to_string(),
         }
     }
-}
 
-impl Serialize for FilmMode {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(&self.to_string())
+    pub fn film_mode(&self) -> &FilmMode {
+        &self.film_mode
     }
-}
 
-impl Serialize for Saturation {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            Self::Normal => serializer.serialize_i8(0),
-            Self::MediumHigh => serializer.serialize_i8(1),
-            Self::VeryHigh => serializer.serialize_i8(3),
-            Self::Highest => serializer.serialize_i8(4),
-            Self::High => serializer.serialize_i8(2),
-            Self::MediumLow => serializer.serialize_i8(-1),
-            Self::Low => serializer.serialize_i8(-2),
-            Self::VeryLow => serializer.serialize_i8(-3),
-            Self::Lowest => serializer.serialize_i8(-4),
-            _ => serializer.serialize_str(&self.to_string()),
-        }
+    pub fn advanced_filter(&self) -> &AdvancedFilter {
+        &self.advanced_filter
     }
-}
 
-impl std::fmt::Display for NoiseReduction {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Strongest => write!(f, "4"),
-            Self::VeryStrong => write!(f, "3"),
-            Self::Strong => write!(f, "2"),
-            Self::MediumStrong => write!(f, "1"),
-            Self::Normal => write!(f, "0"),
-            Self::MediumWeak => write!(f, "-1"),
-            Self::Weak => write!(f, "-2"),
-            Self::VeryWeak => write!(f, "-3"),
-            Self::Weakest => write!(f, "-4"),
-        }
+    /// Exposure, lens and focal length, when the file has them.
+    pub fn shot_info(&self) -> Option<&ShotInfo> {
+        self.shot_info.as_ref()
     }
-}
 
-impl std::fmt::Display for Shadow {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Plus4 => write!(f, "4"),
-            Self::Plus3 => write!(f, "3"),
-            Self::Plus2 => write!(f, "2"),
-            Self::Plus1 => write!(f, "1"),
-            Self::Zero => write!(f, "0"),
-            Self::Minus1 => write!(f, "-1"),
-            Self::Minus2 => write!(f, "-2"),
-        }
+    /// The frame's place in a bracket or burst.
+    pub fn sequence(&self) -> Option<&Sequence> {
+        self.sequence.as_ref()
     }
-}
 
-impl std::fmt::Display for Highlight {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Plus4 => write!(f, "4"),
-            Self::Plus3 => write!(f, "3"),
-            Self::Plus2 => write!(f, "2"),
-            Self::Plus1 => write!(f, "1"),
-            Self::Zero => write!(f, "0"),
-            Self::Minus1 => write!(f, "-1"),
-            Self::Minus2 => write!(f, "-2"),
-        }
+    /// The camera body, when the file identifies it.
+    pub fn body(&self) -> Option<&Body> {
+        self.body.as_ref()
     }
-}
 
-impl std::fmt::Display for Saturation {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Normal => write!(f, "0"),
-            Self::MediumHigh => write!(f, "+1"),
-            Self::VeryHigh => write!(f, "+3"),
-            Self::Highest => write!(f, "+4"),
-            Self::High => write!(f, "+2"),
-            Self::MediumLow => write!(f, "-1"),
-            Self::Low => write!(f, "-2"),
-            Self::NoneBW => write!(f, "None B&W"),
-            Self::BWRed => write!(f, "B&W Red"),
-            Self::BWYellow => write!(f, "B&W Yellow"),
-            Self::BWGreen => write!(f, "B&W Green"),
-            Self::BWSepia => write!(f, "B&W Sepia"),
-            Self::VeryLow => write!(f, "-3"),
-            Self::Lowest => write!(f, "-4"),
-            Self::Acros => write!(f, "Acros"),
-            Self::AcrosRed => write!(f, "Acros Red"),
-            Self::AcrosYellow => write!(f, "Acros Yellow"),
-            Self::AcrosGreen => write!(f, "Acros Green"),
+    /// Hides the serial numbers before the results are shared.
+    pub fn redact(&mut self, redaction: &Redaction) {
+        if let Some(body) = &mut self.body {
+            body.redact(redaction);
+            if *body == Body::default() {
+                self.body = None;
+            }
         }
     }
-}
 
-impl std::fmt::Display for Sharpness {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Softest => write!(f, "-4"),
-            Self::VerySoft => write!(f, "-3"),
-            Self::Soft => write!(f, "-2"),
-            Self::MediumSoft => write!(f, "-1"),
-            Self::Normal => write!(f, "0"),
-            Self::MediumHard => write!(f, "+1"),
-            Self::Hard => write!(f, "+2"),
-            Self::VeryHard => write!(f, "+3"),
-            Self::Hardest => write!(f, "+4"),
-        }
+    /// The raw value of tag 0x1003, which carries either the color setting or
+    /// the monochrome simulation and its filter.
+    pub fn saturation_tag(&self) -> Option<i32> {
+        SaturationTag::of(self).encode()
     }
 }
 
-impl std::fmt::Display for FujifilmSettings {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "Film simulation: {}
-Grain: {:?} {:?}
-Color Chrome: {:?}
-Color Chrome FX Blue: {:?}
-White Balance: {}
-White Balance Fine Tune: R:{} B:{}
-Dynamic Range: {:?}
-Shadow: {}
-Highlight: {}
-Color: {}
-Sharpness: {}
-Noise Reduction: {}
-Clarity: {}",
-            self.film_mode,
-            self.grain_size,
-            self.grain_roughness,
-            self.color_chrome,
-            self.color_chrome_fx_blue,
-            self.white_balance,
-            self.white_balance_fine_tune.red,
-            self.white_balance_fine_tune.blue,
-            self.dynamic_range,
-            self.shadow,
-            self.highlight,
-            self.saturation,
-            self.sharpness,
-            self.noise_reduction,
-            self.clarity,
-        )
+impl fmt::Display for FujifilmSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(label, value)| format!("{}: {}", label, value))
+            .collect();
+        write!(f, "{}", lines.join("\n"))
     }
 }
 
@@ -630,268 +1039,741 @@ Clarity: {}",
 //     sharpness: Sharpness::Normal,
 //     white_balance_fine_tune: WhiteBalanceFineTune { red: 0, blue: 0 },
 //     noise_reduction: NoiseReduction::Normal,
-//     clarity: 0,
-//     shadow: Shadow::Minus2,
-//     highlight: Highlight::Zero,
+//     clarity: Clarity { steps: 0 },
+//     shadow: ToneCurve::from_half_steps(-4),
+//     highlight: ToneCurve::default(),
 //     grain_roughness: GrainRoughness::Off,
 //     grain_size: GrainSize::Off,
 //     color_chrome: ColorChrome::Off,
 //     color_chrome_fx_blue: ColorChromeFxBlue::Off,
 //     film_mode: FilmMode::Provia,
 //     dynamic_range: DynamicRange::Auto,
-//     saturation: Saturation::Normal,
+//     applied_dynamic_range: None,
+//     color_temperature: None,
+//     color: Some(Saturation::Normal),
+//     monochrome_filter: MonochromeFilter::None,
+//     advanced_filter: AdvancedFilter::Off,
+//     shot_info: None,
+//     body: None,
+//     sequence: None,
 // };
 
-fn slurp_string<'a>(data: &'a [u8], offset: &'a mut usize, length: usize) -> Option<&'a str> {
-    match std::str::from_utf8(&data[*offset..(*offset + length)]) {
-        Ok(s) => {
-            *offset += length;
-            Some(s)
-        }
-        Err(_) => None,
-    }
-}
-
-fn read_i32(data: &[u8], offset: usize) -> i32 {
-    let mut num = [0u8; 4];
-    num.copy_from_slice(&data[offset..offset + 4]);
-    i32::from_le_bytes(num)
-}
-
-fn slurp_u16(data: &[u8], offset: &mut usize) -> u16 {
-    let mut num = [0u8; 2];
-    num.copy_from_slice(&data[*offset..*offset + 2]);
-    *offset += 2;
-    u16::from_le_bytes(num)
-}
-
-fn slurp_u32(data: &[u8], offset: &mut usize) -> u32 {
-    let mut num = [0u8; 4];
-    num.copy_from_slice(&data[*offset..*offset + 4]);
-    *offset += 4;
-    u32::from_le_bytes(num)
-}
-
-fn slurp_i32(data: &[u8], offset: &mut usize) -> i32 {
-    let mut num = [0u8; 4];
-    num.copy_from_slice(&data[*offset..*offset + 4]);
-    *offset += 4;
-    i32::from_le_bytes(num)
-}
-
+#[derive(Debug)]
 pub enum FilmError {
     // We failed to read a file or something similar.
-    IO(io::Error),
+    #[cfg(feature = "std")]
+    IO(std::io::Error),
     // Exif parsing failed.
+    #[cfg(feature = "exif")]
     Exif(exif::Error),
     // The provided file isn't a Fujifilm photo.
     NotAFujifilmFile,
     // Unexpected Fujifilm value
     UnexpectedValue(String),
+    // A recipe file in the film simulation directory is malformed.
+    InvalidRecipe(String),
+    // Reading or writing XMP failed.
+    Xmp(String),
+    // The file isn't a JPEG we can rewrite.
+    InvalidJpeg(String),
+    // A file name template has an unknown or unclosed placeholder.
+    InvalidTemplate(String),
+    // An X RAW STUDIO conversion profile is malformed, or the settings can't
+    // be written to one.
+    InvalidProfile(String),
+    // An ExifTool dump isn't the JSON `exiftool -j` writes.
+    InvalidExifToolJson(String),
+    // Reading or writing the catalog database failed.
+    Catalog(String),
 }
 
-impl From<io::Error> for FilmError {
-    fn from(error: io::Error) -> Self {
+impl fmt::Display for FilmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAFujifilmFile => write!(f, "Error: not a Fujifilm file"),
+            #[cfg(feature = "std")]
+            Self::IO(io_err) => write!(f, "I/O error: {}", io_err),
+            #[cfg(feature = "exif")]
+            Self::Exif(exif_error) => write!(f, "Exif parsing error: {}", exif_error),
+            Self::UnexpectedValue(value) => {
+                write!(f, "Found unexpected value while parsing: {}", value)
+            }
+            Self::InvalidRecipe(message) => write!(f, "Invalid recipe: {}", message),
+            Self::Xmp(message) => write!(f, "XMP error: {}", message),
+            Self::InvalidJpeg(message) => write!(f, "Invalid JPEG: {}", message),
+            Self::InvalidTemplate(message) => write!(f, "Invalid template: {}", message),
+            Self::InvalidProfile(message) => write!(f, "Invalid conversion profile: {}", message),
+            Self::InvalidExifToolJson(message) => write!(f, "Invalid ExifTool JSON: {}", message),
+            Self::Catalog(message) => write!(f, "Catalog error: {}", message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for FilmError {
+    fn from(error: std::io::Error) -> Self {
         Self::IO(error)
     }
 }
 
+#[cfg(feature = "exif")]
 impl From<exif::Error> for FilmError {
     fn from(error: exif::Error) -> Self {
         Self::Exif(error)
     }
 }
 
-pub fn get_fujifilm_settings(path: &std::path::Path) -> Result<FujifilmSettings, FilmError> {
+#[cfg(feature = "catalog")]
+impl From<rusqlite::Error> for FilmError {
+    fn from(error: rusqlite::Error) -> Self {
+        Self::Catalog(error.to_string())
+    }
+}
+
+#[cfg(feature = "xmp")]
+impl From<quick_xml::Error> for FilmError {
+    fn from(error: quick_xml::Error) -> Self {
+        Self::Xmp(error.to_string())
+    }
+}
+
+/// Decodes the settings in a Fujifilm MakerNote, e.g. one an embedded
+/// system already cut out of the EXIF data.  Needs neither `std` nor the EXIF
+/// reader.
+pub fn get_fujifilm_settings_from_maker_note(data: &[u8]) -> Result<FujifilmSettings, FilmError> {
+    settings_from_entries(&makernote::read_entries(data)?)
+}
+
+fn settings_from_entries(entries: &[MakerNoteEntry]) -> Result<FujifilmSettings, FilmError> {
     let mut result = FujifilmSettings::new();
+    let mut saturation = None;
+    let mut dynamic_range_setting = None;
+    let mut development_dynamic_range = None;
+    let mut auto_dynamic_range = None;
+    let mut auto_bracketing = AutoBracketing::Off;
+    let mut sequence_number = 0;
 
-    let file = std::fs::File::open(path)?;
-    let mut bufreader = std::io::BufReader::new(&file);
-    let exifreader = exif::Reader::new();
-    let exif = exifreader.read_from_container(&mut bufreader)?;
-
-    for field in exif.fields() {
-        if field.tag.number() == MAKER_NOTES_TAG {
-            match field.value {
-                exif::Value::Undefined(ref v, _index) => {
-                    let mut offset: usize = 0;
-
-                    let fujifilm = slurp_string(v, &mut offset, 8);
-
-                    match fujifilm {
-                        Some(s) => {
-                            if s != "FUJIFILM" {
-                                return Err(FilmError::NotAFujifilmFile);
-                            }
-                        }
-                        None => return Err(FilmError::NotAFujifilmFile),
-                    }
-
-                    while offset < v.len() {
-                        let tag = slurp_u16(v, &mut offset);
-
-                        // Fuji RAF, skip it
-                        if tag == 0xc {
-                            offset += 16;
-                            continue;
-                        }
-
-                        let _data_type = slurp_u16(v, &mut offset);
-                        let _comp_count = slurp_u32(v, &mut offset);
-
-                        match tag {
-                            0xc => {
-                                return Err(FilmError::UnexpectedValue(format!(
-                                    "Unexpected RAF data."
-                                )));
-                            }
-                            0x1001 => {
-                                let data_value = slurp_u32(v, &mut offset);
-                                result.sharpness = Sharpness::from_u16(data_value as u16)?;
-                            }
-                            0x1002 => {
-                                let data_value = slurp_u32(v, &mut offset);
-                                result.white_balance = WhiteBalance::from_u16(data_value as u16)?;
-                            }
-                            0x1003 => {
-                                let data_value = slurp_u32(v, &mut offset);
-                                result.saturation = Saturation::from_u16(data_value as u16)?;
-                            }
-                            0x100a => {
-                                let data_value = slurp_u32(v, &mut offset);
-                                let red = read_i32(v, data_value as usize);
-                                let blue = read_i32(v, (data_value + 4) as usize);
-
-                                let wbft = WhiteBalanceFineTune::from_i32(red, blue);
-                                result.white_balance_fine_tune = wbft;
-                            }
-                            0x100e => {
-                                let data_value = slurp_u32(v, &mut offset);
-                                result.noise_reduction =
-                                    NoiseReduction::from_u16(data_value as u16)?;
-                            }
-                            0x100f => {
-                                let clarity = slurp_i32(v, &mut offset) / 1000;
-                                result.clarity = clarity;
-                            }
-                            0x1040 => {
-                                let shadow = slurp_i32(v, &mut offset);
-                                result.shadow = Shadow::from_i32(shadow)?;
-                            }
-                            0x1041 => {
-                                let highlight = slurp_i32(v, &mut offset);
-                                result.highlight = Highlight::from_i32(highlight)?;
-                            }
-                            0x1047 => {
-                                let roughness = slurp_i32(v, &mut offset);
-                                result.grain_roughness = match roughness {
-                                    0 => GrainRoughness::Off,
-                                    32 => GrainRoughness::Weak,
-                                    64 => GrainRoughness::Strong,
-                                    _ => {
-                                        return Err(FilmError::UnexpectedValue(format!(
-                                            "Failed to parse {} as grain roughness value.",
-                                            roughness
-                                        )))
-                                    }
-                                };
-                            }
-                            0x1048 => {
-                                let color_chrome = slurp_i32(v, &mut offset);
-                                result.color_chrome = match color_chrome {
-                                    0 => ColorChrome::Off,
-                                    32 => ColorChrome::Weak,
-                                    64 => ColorChrome::Strong,
-                                    _ => {
-                                        return Err(FilmError::UnexpectedValue(format!(
-                                            "Failed to parse {} as color chrome value.",
-                                            color_chrome
-                                        )))
-                                    }
-                                };
-                            }
-                            0x104c => {
-                                let size = slurp_u16(v, &mut offset);
-                                offset += 2;
-                                result.grain_size = match size {
-                                    0 => GrainSize::Off,
-                                    16 => GrainSize::Small,
-                                    32 => GrainSize::Large,
-                                    _ => {
-                                        return Err(FilmError::UnexpectedValue(format!(
-                                            "Failed to parse {} as grain size value.",
-                                            size
-                                        )))
-                                    }
-                                };
-                            }
-                            0x104e => {
-                                let color_chrome = slurp_i32(v, &mut offset);
-                                result.color_chrome_fx_blue = match color_chrome {
-                                    0 => ColorChromeFxBlue::Off,
-                                    32 => ColorChromeFxBlue::Weak,
-                                    64 => ColorChromeFxBlue::Strong,
-                                    _ => {
-                                        return Err(FilmError::UnexpectedValue(format!(
-                                            "Failed to parse {} as color chrome fx blue value.",
-                                            color_chrome
-                                        )))
-                                    }
-                                };
-                            }
-                            0x1401 => {
-                                let film = slurp_u16(v, &mut offset);
-                                offset += 2;
-                                result.film_mode = match film {
-                                    0x0 => FilmMode::Provia,
-                                    0x120 => FilmMode::Astia,
-                                    0x400 => FilmMode::Velvia,
-                                    0x500 => FilmMode::ProNegStd,
-                                    0x501 => FilmMode::ProNegHi,
-                                    0x600 => FilmMode::ClassicChrome,
-                                    0x700 => FilmMode::Eterna,
-                                    0x800 => FilmMode::ClassicNegative,
-                                    0xa00 => FilmMode::NostalgicNeg,
-                                    0xb00 => FilmMode::RealaACE,
-                                    _ => return Err(FilmError::UnexpectedValue(format!("hi"))),
-                                };
-                            }
-                            0x1403 => {
-                                let dynamic = slurp_u16(v, &mut offset);
-                                offset += 2;
-                                result.dynamic_range = match dynamic {
-                                    0 => DynamicRange::Auto,
-                                    100 => DynamicRange::DR100,
-                                    200 => DynamicRange::DR200,
-                                    400 => DynamicRange::DR400,
-                                    _ => {
-                                        return Err(FilmError::UnexpectedValue(format!(
-                                            "Failed to parse {} as dynamic range value.",
-                                            dynamic
-                                        )))
-                                    }
-                                };
-                            }
-                            _ => {
-                                let _data_value = slurp_u32(v, &mut offset);
-                            }
-                        }
-                    }
-                }
-                _ => (),
+    for entry in entries {
+        let unexpected =
+            || FilmError::UnexpectedValue(format!("Unexpected data in tag 0x{:04x}.", entry.tag));
+        let short = || entry.u16().map(i32::from).ok_or_else(unexpected);
+        let long = || entry.i32().ok_or_else(unexpected);
+
+        match entry.tag {
+            0x1001 => result.sharpness = Sharpness::decode(short()?)?,
+            0x1002 => result.white_balance = WhiteBalance::decode(short()?)?,
+            0x1005 => result.color_temperature = Some(entry.u16().ok_or_else(unexpected)?),
+            0x1003 => saturation = Some(SaturationTag::decode(short()?)?),
+            0x100a => {
+                let red = entry.i32_at(0).ok_or_else(unexpected)?;
+                let blue = entry.i32_at(1).ok_or_else(unexpected)?;
+                result.white_balance_fine_tune = WhiteBalanceFineTune::decode((red, blue))?;
+            }
+            0x100e => result.noise_reduction = NoiseReduction::decode(short()?)?,
+            0x100f => result.clarity = Clarity::decode(long()?)?,
+            0x1040 => result.shadow = ToneCurve::decode(long()?)?,
+            0x1041 => result.highlight = ToneCurve::decode(long()?)?,
+            0x1047 => result.grain_roughness = GrainRoughness::decode(long()?)?,
+            0x1048 => result.color_chrome = ColorChrome::decode(long()?)?,
+            0x104c => result.grain_size = GrainSize::decode(short()?)?,
+            0x104e => result.color_chrome_fx_blue = ColorChromeFxBlue::decode(long()?)?,
+            0x1100 => {
+                auto_bracketing =
+                    AutoBracketing::decode(short()?).unwrap_or(AutoBracketing::Unknown)
             }
+            0x1101 => sequence_number = entry.u16().ok_or_else(unexpected)?,
+            // A filter we don't know still means the photo doesn't look like
+            // its film simulation, so keep it rather than failing the photo.
+            0x1201 => {
+                result.advanced_filter =
+                    AdvancedFilter::decode(long()?).unwrap_or(AdvancedFilter::Unknown)
+            }
+            0x1401 => result.film_mode = FilmMode::decode(short()?)?,
+            0x1402 => dynamic_range_setting = Some(short()?),
+            0x1403 => development_dynamic_range = Some(DynamicRange::decode(short()?)?),
+            0x140b => auto_dynamic_range = Some(DynamicRange::decode(short()?)?),
+            _ => {}
         }
     }
 
-    if result.film_mode == FilmMode::None {
-        match result.saturation {
-            Saturation::Acros
-            | Saturation::AcrosGreen
-            | Saturation::AcrosRed
-            | Saturation::AcrosYellow => result.film_mode = FilmMode::Acros,
-            _ => {}
+    // Single frames are number 0.
+    if sequence_number > 0 {
+        result.sequence = Some(Sequence {
+            number: sequence_number,
+            auto_bracketing,
+        });
+    }
+
+    // With the dynamic range setting on Auto, the development dynamic range
+    // is what the camera chose.  Older bodies only write the latter, with 0
+    // meaning Auto.
+    if let Some(development) = development_dynamic_range {
+        result.dynamic_range = development;
+    }
+    if dynamic_range_setting == Some(DYNAMIC_RANGE_AUTO) {
+        result.applied_dynamic_range = auto_dynamic_range
+            .or(development_dynamic_range)
+            .filter(|applied| *applied != DynamicRange::Auto);
+        result.dynamic_range = DynamicRange::Auto;
+    }
+
+    match saturation {
+        Some(SaturationTag::Color(color)) => result.color = Some(color),
+        Some(SaturationTag::Monochrome(film_mode, filter)) => {
+            result.film_mode = film_mode;
+            result.monochrome_filter = filter;
+            result.color = None;
         }
+        None => {}
     }
 
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::{maker_note, settings_entries, short, slong};
+
+    fn parse_entries(entries: &[MakerNoteEntry]) -> Result<FujifilmSettings, FilmError> {
+        get_fujifilm_settings_from_maker_note(&maker_note(entries))
+    }
+
+    // Writes the settings and reads them back, through a whole JPEG when
+    // there is an EXIF reader.
+    #[cfg(feature = "exif")]
+    fn round_trip(settings: &FujifilmSettings) -> FujifilmSettings {
+        let jpeg = fixtures::jpeg(settings);
+        get_fujifilm_settings_from_bytes(&jpeg).unwrap()
+    }
+
+    #[cfg(not(feature = "exif"))]
+    fn round_trip(settings: &FujifilmSettings) -> FujifilmSettings {
+        parse_entries(&settings_entries(settings)).unwrap()
+    }
+
+    fn assert_unexpected(result: Result<FujifilmSettings, FilmError>) {
+        match result {
+            Err(FilmError::UnexpectedValue(_)) => {}
+            other => panic!("expected UnexpectedValue, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn round_trips_settings() {
+        let settings = FujifilmSettings {
+            white_balance: WhiteBalance::Daylight,
+            white_balance_fine_tune: WhiteBalanceFineTune { red: 2, blue: -5 },
+            sharpness: Sharpness::MediumHard,
+            noise_reduction: NoiseReduction::Weakest,
+            clarity: Clarity::from_steps(3).unwrap(),
+            shadow: ToneCurve::from_half_steps(-1).unwrap(),
+            highlight: ToneCurve::from_half_steps(3).unwrap(),
+            grain_roughness: GrainRoughness::Weak,
+            grain_size: GrainSize::Large,
+            color_chrome: ColorChrome::Strong,
+            color_chrome_fx_blue: ColorChromeFxBlue::Weak,
+            film_mode: FilmMode::ClassicChrome,
+            dynamic_range: DynamicRange::DR400,
+            applied_dynamic_range: None,
+            color_temperature: None,
+            color: Some(Saturation::VeryHigh),
+            monochrome_filter: MonochromeFilter::None,
+            advanced_filter: AdvancedFilter::Off,
+            shot_info: None,
+            body: None,
+            sequence: None,
+        };
+
+        assert_eq!(round_trip(&settings), settings);
+    }
+
+    #[test]
+    fn decodes_bare_maker_note() {
+        let mut settings = FujifilmSettings::new();
+        settings.film_mode = FilmMode::Acros;
+        settings.color = None;
+        let note = maker_note(&settings_entries(&settings));
+        assert_eq!(
+            get_fujifilm_settings_from_maker_note(&note).unwrap(),
+            settings
+        );
+
+        assert!(matches!(
+            get_fujifilm_settings_from_maker_note(b"not a maker note"),
+            Err(FilmError::NotAFujifilmFile)
+        ));
+    }
+
+    #[test]
+    fn sharpness() {
+        let cases = [
+            (0x0, Sharpness::Softest),
+            (0x1, Sharpness::VerySoft),
+            (0x2, Sharpness::Soft),
+            (0x82, Sharpness::MediumSoft),
+            (0x3, Sharpness::Normal),
+            (0x84, Sharpness::MediumHard),
+            (0x4, Sharpness::Hard),
+            (0x5, Sharpness::VeryHard),
+            (0x6, Sharpness::Hardest),
+        ];
+        for (raw, expected) in cases {
+            let settings = parse_entries(&[short(0x1001, raw)]).unwrap();
+            assert_eq!(settings.sharpness, expected);
+        }
+        assert_unexpected(parse_entries(&[short(0x1001, 0x83)]));
+    }
+
+    #[test]
+    fn white_balance() {
+        let cases = [
+            (0x0, WhiteBalance::Auto),
+            (0x1, WhiteBalance::AutoWhitePriority),
+            (0x2, WhiteBalance::AutoAmbiancePriority),
+            (0x100, WhiteBalance::Daylight),
+            (0x200, WhiteBalance::Cloudy),
+            (0x300, WhiteBalance::DaylightFluorescent),
+            (0x301, WhiteBalance::DayWhiteFluorescent),
+            (0x302, WhiteBalance::WhiteFluorescent),
+            (0x303, WhiteBalance::WarmWhiteFluorescent),
+            (0x304, WhiteBalance::LivingRoomWarmWhiteFluorescent),
+            (0x400, WhiteBalance::Incandescent),
+            (0x500, WhiteBalance::Flash),
+            (0x600, WhiteBalance::Underwater),
+            (0xf00, WhiteBalance::Custom),
+            (0xf01, WhiteBalance::Custom2),
+            (0xf02, WhiteBalance::Custom3),
+            (0xf03, WhiteBalance::Custom4),
+            (0xf04, WhiteBalance::Custom5),
+            (0xff0, WhiteBalance::Kelvin),
+        ];
+        for (raw, expected) in cases {
+            let settings = parse_entries(&[short(0x1002, raw)]).unwrap();
+            assert_eq!(settings.white_balance, expected);
+        }
+        assert_unexpected(parse_entries(&[short(0x1002, 0x700)]));
+    }
+
+    #[test]
+    fn color() {
+        let cases = [
+            (0x0, Saturation::Normal),
+            (0x80, Saturation::MediumHigh),
+            (0xc0, Saturation::VeryHigh),
+            (0xe0, Saturation::Highest),
+            (0x100, Saturation::High),
+            (0x180, Saturation::MediumLow),
+            (0x200, Saturation::Low),
+            (0x4c0, Saturation::VeryLow),
+            (0x4e0, Saturation::Lowest),
+        ];
+        for (raw, expected) in cases {
+            let settings = parse_entries(&[short(0x1401, 0x600), short(0x1003, raw)]).unwrap();
+            assert_eq!(settings.color, Some(expected));
+            assert_eq!(settings.film_mode, FilmMode::ClassicChrome);
+            assert_eq!(settings.monochrome_filter, MonochromeFilter::None);
+        }
+        assert_unexpected(parse_entries(&[short(0x1003, 0x400)]));
+    }
+
+    #[test]
+    fn noise_reduction() {
+        let cases = [
+            (0x0, NoiseReduction::Normal),
+            (0x100, NoiseReduction::Strong),
+            (0x180, NoiseReduction::MediumStrong),
+            (0x1c0, NoiseReduction::VeryStrong),
+            (0x1e0, NoiseReduction::Strongest),
+            (0x200, NoiseReduction::Weak),
+            (0x280, NoiseReduction::MediumWeak),
+            (0x2c0, NoiseReduction::VeryWeak),
+            (0x2e0, NoiseReduction::Weakest),
+        ];
+        for (raw, expected) in cases {
+            let settings = parse_entries(&[short(0x100e, raw)]).unwrap();
+            assert_eq!(settings.noise_reduction, expected);
+        }
+        assert_unexpected(parse_entries(&[short(0x100e, 0x300)]));
+    }
+
+    #[test]
+    fn shadow_and_highlight() {
+        let cases = [
+            (32, -2.0, "-2"),
+            (24, -1.5, "-1.5"),
+            (16, -1.0, "-1"),
+            (8, -0.5, "-0.5"),
+            (0, 0.0, "0"),
+            (-8, 0.5, "+0.5"),
+            (-16, 1.0, "+1"),
+            (-24, 1.5, "+1.5"),
+            (-32, 2.0, "+2"),
+            (-40, 2.5, "+2.5"),
+            (-48, 3.0, "+3"),
+            (-56, 3.5, "+3.5"),
+            (-64, 4.0, "+4"),
+        ];
+        for (raw, value, display) in cases {
+            let settings = parse_entries(&[slong(0x1040, raw), slong(0x1041, raw)]).unwrap();
+            assert_eq!(settings.shadow.value(), value);
+            assert_eq!(settings.highlight.value(), value);
+            assert_eq!(settings.shadow.to_string(), display);
+        }
+
+        for raw in [40, -72, 4] {
+            assert_unexpected(parse_entries(&[slong(0x1040, raw)]));
+            assert_unexpected(parse_entries(&[slong(0x1041, raw)]));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn tone_curve_serde() {
+        let half = ToneCurve::from_half_steps(3).unwrap();
+        let whole = ToneCurve::from_half_steps(-2).unwrap();
+
+        assert_eq!(serde_json::json!(half).to_string(), "1.5");
+        assert_eq!(serde_json::json!(whole).to_string(), "-1");
+        assert_eq!(serde_json::from_str::<ToneCurve>("1.5").unwrap(), half);
+        assert!(serde_json::from_str::<ToneCurve>("1.25").is_err());
+        assert!(serde_json::from_str::<ToneCurve>("4.5").is_err());
+    }
+
+    #[test]
+    fn grain() {
+        let roughness = [
+            (0, GrainRoughness::Off),
+            (32, GrainRoughness::Weak),
+            (64, GrainRoughness::Strong),
+        ];
+        for (raw, expected) in roughness {
+            let settings = parse_entries(&[slong(0x1047, raw)]).unwrap();
+            assert_eq!(settings.grain_roughness, expected);
+        }
+
+        let size = [
+            (0, GrainSize::Off),
+            (16, GrainSize::Small),
+            (32, GrainSize::Large),
+        ];
+        for (raw, expected) in size {
+            let settings = parse_entries(&[short(0x104c, raw)]).unwrap();
+            assert_eq!(settings.grain_size, expected);
+        }
+
+        assert_unexpected(parse_entries(&[slong(0x1047, 16)]));
+        assert_unexpected(parse_entries(&[short(0x104c, 64)]));
+    }
+
+    #[test]
+    fn color_chrome() {
+        let chrome = [
+            (0, ColorChrome::Off),
+            (32, ColorChrome::Weak),
+            (64, ColorChrome::Strong),
+        ];
+        for (raw, expected) in chrome {
+            let settings = parse_entries(&[slong(0x1048, raw)]).unwrap();
+            assert_eq!(settings.color_chrome, expected);
+        }
+
+        let fx_blue = [
+            (0, ColorChromeFxBlue::Off),
+            (32, ColorChromeFxBlue::Weak),
+            (64, ColorChromeFxBlue::Strong),
+        ];
+        for (raw, expected) in fx_blue {
+            let settings = parse_entries(&[slong(0x104e, raw)]).unwrap();
+            assert_eq!(settings.color_chrome_fx_blue, expected);
+        }
+
+        assert_unexpected(parse_entries(&[slong(0x1048, 1)]));
+        assert_unexpected(parse_entries(&[slong(0x104e, 1)]));
+    }
+
+    #[test]
+    fn dynamic_range() {
+        let cases = [
+            (0, DynamicRange::Auto),
+            (100, DynamicRange::DR100),
+            (200, DynamicRange::DR200),
+            (400, DynamicRange::DR400),
+        ];
+        for (raw, expected) in cases {
+            let settings = parse_entries(&[short(0x1403, raw)]).unwrap();
+            assert_eq!(settings.dynamic_range, expected);
+        }
+        assert_unexpected(parse_entries(&[short(0x1403, 800)]));
+    }
+
+    #[test]
+    fn film_mode() {
+        let cases = [
+            (0x0, FilmMode::Provia),
+            (0x120, FilmMode::Astia),
+            (0x400, FilmMode::Velvia),
+            (0x500, FilmMode::ProNegStd),
+            (0x501, FilmMode::ProNegHi),
+            (0x600, FilmMode::ClassicChrome),
+            (0x700, FilmMode::Eterna),
+            (0x800, FilmMode::ClassicNegative),
+            (0x900, FilmMode::EternaBleachBypass),
+            (0xa00, FilmMode::NostalgicNeg),
+            (0xb00, FilmMode::RealaACE),
+        ];
+        for (raw, expected) in cases {
+            let settings = parse_entries(&[short(0x1401, raw)]).unwrap();
+            assert_eq!(settings.film_mode, expected);
+        }
+        assert_unexpected(parse_entries(&[short(0x1401, 0xf00)]));
+    }
+
+    #[test]
+    fn monochrome() {
+        let cases = [
+            (0x300, FilmMode::Monochrome, MonochromeFilter::None),
+            (0x301, FilmMode::Monochrome, MonochromeFilter::Red),
+            (0x302, FilmMode::Monochrome, MonochromeFilter::Yellow),
+            (0x303, FilmMode::Monochrome, MonochromeFilter::Green),
+            (0x310, FilmMode::Sepia, MonochromeFilter::None),
+            (0x500, FilmMode::Acros, MonochromeFilter::None),
+            (0x501, FilmMode::Acros, MonochromeFilter::Red),
+            (0x502, FilmMode::Acros, MonochromeFilter::Yellow),
+            (0x503, FilmMode::Acros, MonochromeFilter::Green),
+        ];
+        for (raw, film_mode, filter) in cases {
+            let settings = parse_entries(&[short(0x1003, raw)]).unwrap();
+            assert_eq!(settings.film_mode, film_mode);
+            assert_eq!(settings.monochrome_filter, filter);
+            assert_eq!(settings.color, None);
+
+            #[cfg(feature = "serde")]
+            assert_eq!(
+                serde_json::json!(settings)["color"],
+                serde_json::Value::Null
+            );
+        }
+
+        // A monochrome saturation value wins over a film mode tag.
+        let settings = parse_entries(&[short(0x1401, 0x600), short(0x1003, 0x500)]).unwrap();
+        assert_eq!(settings.film_mode, FilmMode::Acros);
+    }
+
+    #[test]
+    fn round_trips_monochrome() {
+        let mut settings = FujifilmSettings::new();
+        settings.film_mode = FilmMode::Acros;
+        settings.monochrome_filter = MonochromeFilter::Yellow;
+        settings.color = None;
+
+        assert_eq!(round_trip(&settings), settings);
+        assert!(settings
+            .to_string()
+            .contains("Film simulation: Acros\nMonochrome Filter: Yellow"));
+        assert!(!settings.to_string().contains("Color:"));
+    }
+
+    #[test]
+    fn fine_tune_and_clarity() {
+        let mut fine_tune = 40i32.to_le_bytes().to_vec();
+        fine_tune.extend_from_slice(&(-100i32).to_le_bytes());
+        let settings = parse_entries(&[
+            fixtures::entry(0x100a, 9, 2, fine_tune),
+            slong(0x100f, -2000),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            settings.white_balance_fine_tune,
+            WhiteBalanceFineTune { red: 2, blue: -5 }
+        );
+        assert_eq!(settings.clarity.steps(), -2);
+    }
+
+    #[test]
+    fn not_a_fujifilm_file() {
+        let mut other = maker_note(&[short(0x1001, 0x3)]);
+        other[..8].copy_from_slice(b"OLYMPUS\0");
+        assert!(matches!(
+            get_fujifilm_settings_from_maker_note(&other),
+            Err(FilmError::NotAFujifilmFile)
+        ));
+    }
+
+    #[test]
+    fn truncated_maker_note() {
+        let mut truncated = maker_note(&[short(0x1001, 0x3), short(0x1002, 0x0)]);
+        truncated.truncate(truncated.len() - 8);
+        assert_unexpected(get_fujifilm_settings_from_maker_note(&truncated));
+
+        // Offsets near u32::MAX wrap on 32-bit targets.
+        let mut far_ifd = b"FUJIFILM".to_vec();
+        far_ifd.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_unexpected(get_fujifilm_settings_from_maker_note(&far_ifd));
+
+        let mut far_value = b"FUJIFILM".to_vec();
+        far_value.extend_from_slice(&12u32.to_le_bytes());
+        far_value.extend_from_slice(&1u16.to_le_bytes());
+        far_value.extend_from_slice(&0x1001u16.to_le_bytes());
+        far_value.extend_from_slice(&2u16.to_le_bytes());
+        far_value.extend_from_slice(&8u32.to_le_bytes());
+        far_value.extend_from_slice(&0xffff_fffcu32.to_le_bytes());
+        assert_unexpected(get_fujifilm_settings_from_maker_note(&far_value));
+    }
+
+    #[test]
+    fn applied_auto_settings() {
+        let settings = parse_entries(&[
+            short(0x1002, 0x0),
+            short(0x1005, 5200),
+            short(0x1402, 0),
+            short(0x1403, 200),
+        ])
+        .unwrap();
+        assert_eq!(settings.dynamic_range, DynamicRange::Auto);
+        assert_eq!(settings.applied_dynamic_range, Some(DynamicRange::DR200));
+        assert!(settings
+            .to_string()
+            .contains("White Balance: Auto (5200K applied)"));
+        assert!(settings
+            .to_string()
+            .contains("Dynamic Range: Auto (DR200 applied)"));
+        assert_eq!(round_trip(&settings), settings);
+
+        // Picked by hand, there's nothing to resolve.
+        let settings = parse_entries(&[short(0x1402, 1), short(0x1403, 400)]).unwrap();
+        assert_eq!(settings.dynamic_range, DynamicRange::DR400);
+        assert_eq!(settings.applied_dynamic_range, None);
+
+        let settings = parse_entries(&[short(0x1002, 0xff0), short(0x1005, 5500)]).unwrap();
+        assert!(settings
+            .to_string()
+            .contains("White Balance: Kelvin (5500K)"));
+
+        let settings = parse_entries(&[short(0x1002, 0x100), short(0x1005, 5200)]).unwrap();
+        assert!(settings.to_string().contains("White Balance: Daylight\n"));
+    }
+
+    #[test]
+    fn advanced_filter() {
+        let settings = parse_entries(&[short(0x1401, 0x600), slong(0x1201, 0x30000)]).unwrap();
+        assert_eq!(settings.advanced_filter, AdvancedFilter::ToyCamera);
+        assert!(settings
+            .to_string()
+            .contains("Film simulation: Classic Chrome\nAdvanced Filter: Toy Camera"));
+        assert_eq!(round_trip(&settings), settings);
+
+        let settings = parse_entries(&[slong(0x1201, 0x60004)]).unwrap();
+        assert_eq!(settings.advanced_filter, AdvancedFilter::PartialColorBlue);
+        #[cfg(feature = "serde")]
+        assert_eq!(
+            serde_json::json!(settings)["advanced_filter"],
+            "PartialColorBlue"
+        );
+
+        assert!(!FujifilmSettings::new()
+            .to_string()
+            .contains("Advanced Filter"));
+
+        let settings = parse_entries(&[slong(0x1201, 0x60007)]).unwrap();
+        assert_eq!(settings.advanced_filter, AdvancedFilter::Unknown);
+        assert!(settings.to_string().contains("Advanced Filter: Unknown"));
+    }
+
+    #[test]
+    fn sequence() {
+        let settings = parse_entries(&[short(0x1100, 1), short(0x1101, 2)]).unwrap();
+        assert_eq!(
+            settings.sequence,
+            Some(Sequence {
+                number: 2,
+                auto_bracketing: AutoBracketing::On,
+            })
+        );
+        assert_eq!(round_trip(&settings), settings);
+
+        // Single frames carry sequence number 0.
+        let settings = parse_entries(&[short(0x1100, 0), short(0x1101, 0)]).unwrap();
+        assert_eq!(settings.sequence, None);
+
+        let settings = parse_entries(&[short(0x1100, 3), short(0x1101, 1)]).unwrap();
+        assert_eq!(
+            settings.sequence.map(|s| s.auto_bracketing),
+            Some(AutoBracketing::Unknown)
+        );
+    }
+
+    #[test]
+    fn lists_every_entry() {
+        let entries = [short(0x1001, 0x84), slong(0x1040, -16), short(0x9999, 7)];
+        let listed = makernote::read_entries(&maker_note(&entries)).unwrap();
+
+        assert_eq!(listed.len(), 3);
+        assert_eq!(listed[0].name(), Some("Sharpness"));
+        assert_eq!(listed[0].value_string(), "132");
+        assert_eq!(listed[1].value_string(), "-16");
+        assert_eq!(listed[2].name(), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn matches_recipe() {
+        let recipe: Recipe = serde_json::from_str(
+            r#"{
+                "name": "Street Chrome",
+                "settings": {
+                    "film_mode": "ClassicChrome",
+                    "grain_roughness": "Weak",
+                    "grain_size": "Small",
+                    "color_chrome": "Strong",
+                    "color_chrome_fx_blue": "Off",
+                    "white_balance": "Auto",
+                    "white_balance_fine_tune": {"red": 2, "blue": -5},
+                    "dynamic_range": "DR200",
+                    "shadow": 1,
+                    "highlight": -0.5,
+                    "color": "MediumHigh",
+                    "sharpness": "MediumHard",
+                    "noise_reduction": "Weakest",
+                    "clarity": 0
+                }
+            }"#,
+        )
+        .unwrap();
+        let recipes = [recipe];
+
+        let mut settings = round_trip(&recipes[0].settings);
+        assert_eq!(
+            find_recipe(&settings, &recipes).map(|r| r.name.as_str()),
+            Some("Street Chrome")
+        );
+
+        // Auto that came out as the recipe's DR200, at whatever temperature
+        // the camera measured, is still the recipe.
+        settings.dynamic_range = DynamicRange::Auto;
+        settings.applied_dynamic_range = Some(DynamicRange::DR200);
+        settings.color_temperature = Some(5200);
+        assert!(find_recipe(&settings, &recipes).is_some());
+
+        settings.applied_dynamic_range = Some(DynamicRange::DR400);
+        assert!(find_recipe(&settings, &recipes).is_none());
+        settings.applied_dynamic_range = Some(DynamicRange::DR200);
+
+        // Not even a recipe that asks for the filter matches.
+        settings.advanced_filter = AdvancedFilter::SoftFocus;
+        let filtered = Recipe {
+            name: "Soft".to_string(),
+            settings: settings.clone(),
+        };
+        assert!(find_recipe(&settings, &recipes).is_none());
+        assert!(find_recipe(&settings, core::slice::from_ref(&filtered)).is_none());
+        settings.advanced_filter = AdvancedFilter::Unknown;
+        assert!(find_recipe(&settings, &recipes).is_none());
+        settings.advanced_filter = AdvancedFilter::Off;
+
+        settings.applied_dynamic_range = Some(DynamicRange::DR200);
+        settings.clarity = Clarity::from_steps(1).unwrap();
+        assert!(find_recipe(&settings, &recipes).is_none());
+    }
+}
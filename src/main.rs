@@ -15,48 +15,857 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use film_detect::FilmError;
-use serde_json;
 use std::path;
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Filename to operate on
-    file: String,
+    #[arg(required = true)]
+    file: Option<String>,
 
     /// Output JSON
-    #[arg(long)]
+    #[arg(long, global = true)]
     json: bool,
 
-    // Sets a film simulation directory
-    #[arg(short, long, value_name = "DIR")]
+    /// How settings are named in JSON output
+    #[arg(long, value_enum, value_name = "STYLE", default_value_t = JsonStyle::FilmDetect, global = true)]
+    json_style: JsonStyle,
+
+    /// Directory of recipes to match photos against
+    #[arg(short, long, value_name = "DIR", global = true)]
     simulations: Option<path::PathBuf>,
+
+    /// Create or update an XMP sidecar next to the file
+    #[arg(long)]
+    write_xmp: bool,
+
+    /// Add the film simulation and recipe to the JPEG's own XMP keywords
+    #[arg(long)]
+    embed_keywords: bool,
+
+    /// Show which keywords would be embedded without changing the file
+    #[arg(long, requires = "embed_keywords")]
+    dry_run: bool,
+
+    /// Save the settings as an X RAW STUDIO conversion profile (.FP3)
+    #[arg(long, value_name = "FILE")]
+    export_fp: Option<path::PathBuf>,
+
+    /// Leave out the camera's serial numbers, or hash them
+    #[arg(
+        long,
+        value_enum,
+        value_name = "HOW",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "omit",
+        global = true
+    )]
+    redact: Option<Redact>,
+
+    /// The secret key for --redact=hash.  The same salt gives the same
+    /// hashes, so keep it to group photos across runs
+    #[arg(long, value_name = "SALT", global = true)]
+    redact_salt: Option<String>,
 }
 
-fn main() {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum Command {
+    /// List every MakerNote entry in the file
+    Dump {
+        /// Filename to operate on
+        file: String,
+    },
+    /// List the settings of every image in a multi-picture (MPO) file
+    Images {
+        /// Filename to operate on
+        file: String,
+    },
+    /// Read settings from an ExifTool JSON dump (exiftool -j -G)
+    Exiftool {
+        /// The JSON file ExifTool wrote
+        file: path::PathBuf,
+    },
+    /// Keep a catalog of parsed files, and report on it
+    Catalog {
+        #[command(subcommand)]
+        command: CatalogCommand,
+    },
+    /// Print the settings of photos as they land in a directory
+    Watch {
+        /// The directory to watch, with its subdirectories
+        dir: path::PathBuf,
 
-    match film_detect::get_fujifilm_settings(path::Path::new(&cli.file)) {
-        Ok(fujifilm_settings) => {
-            if cli.json {
-                println!("{}", serde_json::json!(fujifilm_settings));
+        /// Run a command for every photo, with its result as JSON on standard input
+        #[arg(long, value_name = "COMMAND")]
+        exec: Option<String>,
+    },
+    /// Answer HTTP requests for the settings of uploaded or local photos
+    Serve {
+        /// Address and port to listen on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+        bind: String,
+        /// Directory GET requests may read photos from; without it only uploads are answered
+        #[arg(long, value_name = "DIR")]
+        root: Option<path::PathBuf>,
+    },
+    /// Group files into film simulation brackets, brackets and bursts
+    Batch {
+        /// Files to group, in the order the camera wrote them
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+    },
+    /// Move, copy or link files into a layout built from their settings
+    Organize {
+        /// Files to organize
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+
+        /// Where the layout is created
+        #[arg(short, long, value_name = "DIR", default_value = ".")]
+        destination: path::PathBuf,
+
+        /// Layout template; {film_mode}, {recipe}, {date} and {name} are filled in
+        #[arg(short, long, default_value = film_detect::DEFAULT_TEMPLATE)]
+        template: String,
+
+        #[arg(long, value_enum, default_value_t = Mode::Move)]
+        mode: Mode,
+
+        /// What to do when the target file already exists
+        #[arg(long, value_enum, default_value_t = OnCollision::Skip)]
+        on_collision: OnCollision,
+
+        /// Show where files would go without touching them
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CatalogCommand {
+    /// Add new and changed files to the catalog
+    Scan {
+        /// The catalog, created if it doesn't exist
+        catalog: path::PathBuf,
+
+        /// Files and directories to scan; directories are scanned recursively
+        #[arg(required = true)]
+        paths: Vec<path::PathBuf>,
+    },
+    /// Count film simulations and recipes without opening the files
+    Report {
+        /// The catalog
+        catalog: path::PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Mode {
+    Move,
+    Copy,
+    Hardlink,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum JsonStyle {
+    /// Our own names: "film_mode": "ClassicChrome"
+    FilmDetect,
+    /// ExifTool's tag names and values: "MakerNotes:FilmMode": "Classic Chrome"
+    Exiftool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Redact {
+    Omit,
+    Hash,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OnCollision {
+    Skip,
+    Rename,
+    Overwrite,
+}
+
+fn error_message(e: FilmError) -> String {
+    e.to_string()
+}
+
+// Failures go to standard error with a non-zero status, so scripts and
+// --json readers can tell them apart from output.
+fn report_error(e: FilmError) -> ! {
+    eprintln!("{}", error_message(e));
+    std::process::exit(1);
+}
+
+// Hashing serial numbers without a secret would only hide them from people
+// who don't try, so --redact=hash needs a salt.
+fn redaction(cli: &Cli) -> Option<film_detect::Redaction> {
+    match (cli.redact?, &cli.redact_salt) {
+        (Redact::Omit, _) => Some(film_detect::Redaction::Omit),
+        (Redact::Hash, Some(salt)) => Some(film_detect::Redaction::Hash(salt.clone())),
+        (Redact::Hash, None) => Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "--redact=hash needs --redact-salt <SALT>",
+            )
+            .exit(),
+    }
+}
+
+fn redact(
+    settings: &mut film_detect::FujifilmSettings,
+    redaction: Option<&film_detect::Redaction>,
+) {
+    if let Some(redaction) = redaction {
+        settings.redact(redaction);
+    }
+}
+
+fn dump(file: &str, json: bool, redaction: Option<&film_detect::Redaction>) {
+    match film_detect::get_maker_note_entries(path::Path::new(file)) {
+        Ok(mut entries) => {
+            if let Some(redaction) = redaction {
+                film_detect::redact_entries(&mut entries, redaction);
+            }
+            if json {
+                println!("{}", serde_json::json!(entries));
             } else {
-                println!("{}", fujifilm_settings);
+                for entry in entries {
+                    println!("{}", entry);
+                }
+            }
+        }
+        Err(e) => report_error(e),
+    }
+}
+
+fn images(file: &str, json: bool, style: JsonStyle, redaction: Option<&film_detect::Redaction>) {
+    let images = match film_detect::get_embedded_settings(path::Path::new(file)) {
+        Ok(images) => images,
+        Err(e) => report_error(e),
+    };
+
+    let mut output = vec![];
+    for (n, image) in images.into_iter().enumerate() {
+        let settings = image.settings.map(|mut settings| {
+            redact(&mut settings, redaction);
+            settings
+        });
+
+        if json {
+            let mut value = serde_json::json!({"index": image.index, "kind": image.kind});
+            match settings {
+                Ok(settings) => value["settings"] = settings_json(&settings, style),
+                Err(e) => value["error"] = serde_json::json!(error_message(e)),
+            }
+            output.push(value);
+            continue;
+        }
+
+        if n > 0 {
+            println!();
+        }
+        println!("Image {}: {}", image.index, image.kind);
+        match settings {
+            Ok(settings) => println!("{}", settings),
+            Err(e) => println!("{}", error_message(e)),
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::json!(output));
+    }
+}
+
+fn settings_json(settings: &film_detect::FujifilmSettings, style: JsonStyle) -> serde_json::Value {
+    match style {
+        JsonStyle::FilmDetect => serde_json::json!(settings),
+        JsonStyle::Exiftool => serde_json::Value::Object(film_detect::exiftool_json(settings)),
+    }
+}
+
+fn print_settings(settings: &film_detect::FujifilmSettings, recipe: Option<&film_detect::Recipe>) {
+    println!("{}", settings);
+    if let Some(recipe) = recipe {
+        println!("Recipe: {}", recipe.name);
+    }
+    if let Some(shot_info) = settings.shot_info() {
+        println!("\n{}", shot_info);
+    }
+    if let Some(sequence) = settings.sequence() {
+        println!("\nSequence: {}", sequence);
+    }
+    if let Some(body) = settings.body() {
+        println!("\n{}", body);
+    }
+}
+
+fn exiftool(
+    file: &path::Path,
+    recipes: &[film_detect::Recipe],
+    json: bool,
+    style: JsonStyle,
+    redaction: Option<&film_detect::Redaction>,
+) {
+    let records = match film_detect::read_exiftool_json(file) {
+        Ok(records) => records,
+        Err(e) => report_error(e),
+    };
+
+    let mut output = vec![];
+    for (n, record) in records.into_iter().enumerate() {
+        let settings = record.settings.map(|mut settings| {
+            redact(&mut settings, redaction);
+            settings
+        });
+
+        if json {
+            let mut value = serde_json::json!({"file": record.source_file.to_string_lossy()});
+            match settings {
+                Ok(settings) => {
+                    let recipe = film_detect::find_recipe(&settings, recipes);
+                    value["settings"] = settings_json(&settings, style);
+                    value["recipe"] = serde_json::json!(recipe.map(|r| &r.name));
+                }
+                Err(e) => value["error"] = serde_json::json!(error_message(e)),
+            }
+            output.push(value);
+            continue;
+        }
+
+        if n > 0 {
+            println!();
+        }
+        println!("{}:", record.source_file.display());
+        match settings {
+            Ok(settings) => print_settings(&settings, film_detect::find_recipe(&settings, recipes)),
+            Err(e) => println!("{}", error_message(e)),
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::json!(output));
+    }
+}
+
+fn catalog_scan(catalog: &path::Path, paths: &[path::PathBuf], json: bool) {
+    let summary = film_detect::Catalog::open(catalog)
+        .and_then(|mut catalog| catalog.scan(paths))
+        .unwrap_or_else(|e| report_error(e));
+
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({
+                "parsed": summary.parsed,
+                "unchanged": summary.unchanged,
+                "failed": summary.failed,
+                "removed": summary.removed,
+            })
+        );
+    } else {
+        println!(
+            "Parsed {}, unchanged {}, failed {}, removed {}",
+            summary.parsed, summary.unchanged, summary.failed, summary.removed
+        );
+    }
+}
+
+fn catalog_report(
+    catalog: &path::Path,
+    recipes: &[film_detect::Recipe],
+    json: bool,
+    style: JsonStyle,
+    redaction: Option<&film_detect::Redaction>,
+) {
+    let mut entries = film_detect::Catalog::open(catalog)
+        .and_then(|catalog| catalog.entries())
+        .unwrap_or_else(|e| report_error(e));
+    for entry in &mut entries {
+        if let Ok(settings) = &mut entry.settings {
+            redact(settings, redaction);
+        }
+    }
+
+    if json {
+        let output: Vec<serde_json::Value> = entries
+            .iter()
+            .map(|entry| {
+                let mut value = serde_json::json!({
+                    "file": entry.path.to_string_lossy(),
+                    "size": entry.size,
+                    "modified": entry.modified,
+                    "hash": entry.hash,
+                });
+                match &entry.settings {
+                    Ok(settings) => {
+                        let recipe = film_detect::find_recipe(settings, recipes);
+                        value["settings"] = settings_json(settings, style);
+                        value["recipe"] = serde_json::json!(recipe.map(|r| &r.name));
+                    }
+                    Err(error) => value["error"] = serde_json::json!(error),
+                }
+                value
+            })
+            .collect();
+        println!("{}", serde_json::json!(output));
+        return;
+    }
+
+    // Counts in the order each name first shows up.
+    fn count(counts: &mut Vec<(String, usize)>, name: String) {
+        match counts.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((name, 1)),
+        }
+    }
+
+    let mut film_modes = vec![];
+    let mut recipe_counts = vec![];
+    let mut failed = 0;
+    for entry in &entries {
+        match &entry.settings {
+            Ok(settings) => {
+                count(&mut film_modes, settings.film_mode().to_string());
+                if let Some(recipe) = film_detect::find_recipe(settings, recipes) {
+                    count(&mut recipe_counts, recipe.name.clone());
+                }
             }
+            Err(_) => failed += 1,
+        }
+    }
+    film_modes.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    recipe_counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    println!("Files: {}", entries.len());
+    println!("\nFilm simulations:");
+    for (name, count) in film_modes {
+        println!("  {}: {}", name, count);
+    }
+    if !recipe_counts.is_empty() {
+        println!("\nRecipes:");
+        for (name, count) in recipe_counts {
+            println!("  {}: {}", name, count);
         }
-        Err(e) => {
-            let message = match e {
-                FilmError::NotAFujifilmFile => format!("Error: not a Fujifilm file"),
-                FilmError::IO(io_err) => format!("I/O error: {}", io_err),
-                FilmError::Exif(exif_error) => format!("Exif parsing error: {}", exif_error),
-                FilmError::UnexpectedValue(value) => {
-                    format!("Found unexpected value while parsing: {}", value)
+    }
+    if failed > 0 {
+        println!("\nNot parsed: {}", failed);
+    }
+}
+
+fn watch(
+    dir: &path::Path,
+    exec: Option<&str>,
+    recipes: &[film_detect::Recipe],
+    json: bool,
+    style: JsonStyle,
+    redaction: Option<&film_detect::Redaction>,
+) {
+    let mut watcher = film_detect::Watcher::new(dir).unwrap_or_else(|e| report_error(e));
+    eprintln!("Watching {}", dir.display());
+
+    loop {
+        let events = watcher.wait().unwrap_or_else(|e| report_error(e));
+        for event in events {
+            let file = match event {
+                film_detect::WatchEvent::Photo(file) => file,
+                film_detect::WatchEvent::Skipped(dir, e) => {
+                    eprintln!("{}: {}", dir.display(), error_message(e));
+                    continue;
+                }
+            };
+            let settings = film_detect::get_fujifilm_settings(&file).map(|mut settings| {
+                redact(&mut settings, redaction);
+                settings
+            });
+            let recipe = settings
+                .as_ref()
+                .ok()
+                .and_then(|settings| film_detect::find_recipe(settings, recipes));
+
+            let mut value = serde_json::json!({"file": file.to_string_lossy()});
+            match &settings {
+                Ok(settings) => {
+                    value["settings"] = settings_json(settings, style);
+                    value["recipe"] = serde_json::json!(recipe.map(|r| &r.name));
                 }
+                Err(e) => value["error"] = serde_json::json!(e.to_string()),
+            }
+
+            // One line per photo, so the output can be followed as it grows.
+            if json {
+                println!("{}", value);
+            } else {
+                match (&settings, recipe) {
+                    (Ok(settings), Some(recipe)) => {
+                        println!(
+                            "{}: {} ({})",
+                            file.display(),
+                            settings.film_mode(),
+                            recipe.name
+                        )
+                    }
+                    (Ok(settings), None) => {
+                        println!("{}: {}", file.display(), settings.film_mode())
+                    }
+                    (Err(e), _) => println!("{}: {}", file.display(), e),
+                }
+            }
+
+            if let Some(command) = exec {
+                if let Err(e) = run_command(command, &file, &value) {
+                    eprintln!("{}: {}: {}", file.display(), command, e);
+                }
+            }
+        }
+    }
+}
+
+// Runs `command` through the shell with the photo's JSON on standard input
+// and its path in $FILM_DETECT_FILE.
+fn run_command(command: &str, file: &path::Path, value: &serde_json::Value) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut shell = if cfg!(windows) {
+        let mut shell = std::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell
+        .arg(command)
+        .env("FILM_DETECT_FILE", file)
+        .stdin(std::process::Stdio::piped())
+        .spawn()?;
+
+    // A command that doesn't read its input closes the pipe early.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "{}", value);
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("exited with {}", status)));
+    }
+    Ok(())
+}
+
+fn batch(
+    files: &[path::PathBuf],
+    recipes: &[film_detect::Recipe],
+    json: bool,
+    style: JsonStyle,
+    redaction: Option<&film_detect::Redaction>,
+) {
+    let mut photos = vec![];
+    let mut errors = vec![];
+    for file in files {
+        match film_detect::get_fujifilm_settings(file) {
+            Ok(mut settings) => {
+                redact(&mut settings, redaction);
+                photos.push((file.clone(), settings))
+            }
+            Err(e) => errors.push((file, error_message(e))),
+        }
+    }
+
+    let groups = film_detect::group_sequences(photos);
+    let recipe_name = |settings| film_detect::find_recipe(settings, recipes).map(|r| &r.name);
+
+    if json {
+        let mut output: Vec<serde_json::Value> = groups
+            .iter()
+            .map(|group| {
+                let files: Vec<serde_json::Value> = group
+                    .photos
+                    .iter()
+                    .map(|(file, settings)| match style {
+                        JsonStyle::FilmDetect => serde_json::json!({
+                            "file": file.to_string_lossy(),
+                            "film_mode": settings.film_mode(),
+                            "recipe": recipe_name(settings),
+                        }),
+                        // The way ExifTool lists a file.
+                        JsonStyle::Exiftool => {
+                            let mut value = settings_json(settings, style);
+                            value["SourceFile"] = serde_json::json!(file.to_string_lossy());
+                            if let Some(recipe) = recipe_name(settings) {
+                                value["FilmDetect:Recipe"] = serde_json::json!(recipe);
+                            }
+                            value
+                        }
+                    })
+                    .collect();
+                serde_json::json!({
+                    "kind": group.kind,
+                    "summary": group.summary(),
+                    "files": files,
+                })
+            })
+            .collect();
+        output.extend(errors.iter().map(
+            |(file, error)| serde_json::json!({"file": file.to_string_lossy(), "error": error}),
+        ));
+        println!("{}", serde_json::json!(output));
+        return;
+    }
+
+    let describe = |file: &path::Path, settings| match recipe_name(settings) {
+        Some(recipe) => format!("{}: {} ({})", file.display(), settings.film_mode(), recipe),
+        None => format!("{}: {}", file.display(), settings.film_mode()),
+    };
+    for group in &groups {
+        if group.kind == film_detect::GroupKind::Single {
+            let (file, settings) = &group.photos[0];
+            println!("{}", describe(file, settings));
+            continue;
+        }
+        println!("{}", group.summary());
+        for (file, settings) in &group.photos {
+            println!("  {}", describe(file, settings));
+        }
+    }
+    for (file, error) in errors {
+        println!("{}: {}", file.display(), error);
+    }
+}
+
+fn organize(
+    files: &[path::PathBuf],
+    options: &film_detect::OrganizeOptions,
+    recipes: &[film_detect::Recipe],
+    json: bool,
+) {
+    // Catch template mistakes before touching any files.
+    let placeholders = ["film_mode", "recipe", "date", "name"].map(|k| (k, String::new()));
+    if let Err(e) = film_detect::render_template(&options.template, &placeholders) {
+        report_error(e);
+    }
+
+    let results = film_detect::organize(files, options, recipes);
+
+    if json {
+        let output: Vec<serde_json::Value> = files
+            .iter()
+            .zip(results)
+            .map(|(file, result)| match result {
+                Ok(placement) => serde_json::json!({
+                    "source": placement.source.to_string_lossy(),
+                    "target": placement.target.map(|t| t.to_string_lossy().into_owned()),
+                }),
+                Err(e) => serde_json::json!({
+                    "source": file.to_string_lossy(),
+                    "error": error_message(e),
+                }),
+            })
+            .collect();
+        println!("{}", serde_json::json!(output));
+        return;
+    }
+
+    let verb = if options.dry_run { "would go to" } else { "->" };
+    for (file, result) in files.iter().zip(results) {
+        match result {
+            Ok(film_detect::Placement {
+                target: Some(target),
+                ..
+            }) => println!("{} {} {}", file.display(), verb, target.display()),
+            Ok(_) => println!("{}: skipped, target exists", file.display()),
+            Err(e) => println!("{}: {}", file.display(), error_message(e)),
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let redaction = redaction(&cli);
+
+    let recipes = match &cli.simulations {
+        Some(dir) => film_detect::load_recipes(dir).unwrap_or_else(|e| report_error(e)),
+        None => vec![],
+    };
+
+    match &cli.command {
+        Some(Command::Dump { file }) => {
+            dump(file, cli.json, redaction.as_ref());
+            return;
+        }
+        Some(Command::Images { file }) => {
+            images(file, cli.json, cli.json_style, redaction.as_ref());
+            return;
+        }
+        Some(Command::Exiftool { file }) => {
+            exiftool(file, &recipes, cli.json, cli.json_style, redaction.as_ref());
+            return;
+        }
+        Some(Command::Catalog { command }) => {
+            match command {
+                CatalogCommand::Scan { catalog, paths } => catalog_scan(catalog, paths, cli.json),
+                CatalogCommand::Report { catalog } => catalog_report(
+                    catalog,
+                    &recipes,
+                    cli.json,
+                    cli.json_style,
+                    redaction.as_ref(),
+                ),
+            }
+            return;
+        }
+        Some(Command::Watch { dir, exec }) => {
+            watch(
+                dir,
+                exec.as_deref(),
+                &recipes,
+                cli.json,
+                cli.json_style,
+                redaction.as_ref(),
+            );
+            return;
+        }
+        Some(Command::Serve { bind, root }) => {
+            eprintln!("Listening on http://{}", bind);
+            if let Err(e) = film_detect::serve(bind, &recipes, redaction, root.as_deref()) {
+                report_error(e);
+            }
+            return;
+        }
+        Some(Command::Batch { files }) => {
+            batch(
+                files,
+                &recipes,
+                cli.json,
+                cli.json_style,
+                redaction.as_ref(),
+            );
+            return;
+        }
+        Some(Command::Organize {
+            files,
+            destination,
+            template,
+            mode,
+            on_collision,
+            dry_run,
+        }) => {
+            let options = film_detect::OrganizeOptions {
+                template: template.clone(),
+                destination: destination.clone(),
+                mode: match mode {
+                    Mode::Move => film_detect::TransferMode::Move,
+                    Mode::Copy => film_detect::TransferMode::Copy,
+                    Mode::Hardlink => film_detect::TransferMode::Hardlink,
+                },
+                collision: match on_collision {
+                    OnCollision::Skip => film_detect::Collision::Skip,
+                    OnCollision::Rename => film_detect::Collision::Rename,
+                    OnCollision::Overwrite => film_detect::Collision::Overwrite,
+                },
+                dry_run: *dry_run,
             };
-            println!("{}", message);
-            std::process::exit(0);
+            organize(files, &options, &recipes, cli.json);
+            return;
+        }
+        None => {}
+    }
+
+    // clap makes the file required unless a subcommand is given
+    let file = cli.file.unwrap_or_default();
+
+    let file = path::Path::new(&file);
+
+    match film_detect::get_fujifilm_settings(file) {
+        Ok(mut fujifilm_settings) => {
+            redact(&mut fujifilm_settings, redaction.as_ref());
+
+            let recipe = film_detect::find_recipe(&fujifilm_settings, &recipes);
+
+            if cli.write_xmp {
+                let recipe_name = recipe.map(|r| r.name.as_str());
+                match film_detect::write_xmp_sidecar(file, &fujifilm_settings, recipe_name) {
+                    Ok(sidecar) => eprintln!("Wrote {}", sidecar.display()),
+                    Err(e) => report_error(e),
+                }
+            }
+
+            if let Some(profile) = &cli.export_fp {
+                // Named after the recipe, so it is easy to find in X RAW STUDIO.
+                let label = recipe
+                    .map(|r| r.name.clone())
+                    .or_else(|| file.file_stem().map(|s| s.to_string_lossy().to_string()))
+                    .unwrap_or_default();
+                match film_detect::write_profile(profile, &fujifilm_settings, &label) {
+                    Ok(()) => eprintln!("Wrote {}", profile.display()),
+                    Err(e) => report_error(e),
+                }
+            }
+
+            if cli.embed_keywords {
+                let recipe_name = recipe.map(|r| r.name.as_str());
+                let keywords = film_detect::keywords(&fujifilm_settings, recipe_name);
+                match film_detect::embed_keywords(file, &keywords, cli.dry_run) {
+                    Ok(result) if result.added.is_empty() && result.removed.is_empty() => {
+                        eprintln!("{} already has its keywords", result.path.display())
+                    }
+                    Ok(result) => {
+                        let changes = [
+                            ("Would add", "Added", "to", &result.added),
+                            ("Would remove", "Removed", "from", &result.removed),
+                        ];
+                        for (would, did, preposition, keywords) in changes {
+                            if !keywords.is_empty() {
+                                eprintln!(
+                                    "{} keywords {} {} {}",
+                                    if cli.dry_run { would } else { did },
+                                    keywords.join(", "),
+                                    preposition,
+                                    result.path.display()
+                                );
+                            }
+                        }
+                    }
+                    Err(e) => report_error(e),
+                }
+            }
+
+            if cli.json {
+                let mut output = settings_json(&fujifilm_settings, cli.json_style);
+                match (cli.json_style, recipe) {
+                    (JsonStyle::FilmDetect, Some(recipe)) => {
+                        output["recipe"] = serde_json::json!(recipe.name)
+                    }
+                    // The way ExifTool lists a file.
+                    (JsonStyle::Exiftool, recipe) => {
+                        output["SourceFile"] = serde_json::json!(file.to_string_lossy());
+                        if let Some(recipe) = recipe {
+                            output["FilmDetect:Recipe"] = serde_json::json!(recipe.name);
+                        }
+                    }
+                    (JsonStyle::FilmDetect, None) => {}
+                }
+                match cli.json_style {
+                    JsonStyle::FilmDetect => println!("{}", output),
+                    JsonStyle::Exiftool => println!("{}", serde_json::json!([output])),
+                }
+            } else {
+                print_settings(&fujifilm_settings, recipe);
+            }
         }
+        Err(e) => report_error(e),
     }
 }
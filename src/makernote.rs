@@ -0,0 +1,331 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::FilmError;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+
+// Every Fujifilm MakerNote starts with this, followed by a little-endian
+// offset to the IFD.  All offsets in the IFD are relative to the start of the
+// MakerNote.
+const HEADER: &str = "FUJIFILM";
+
+/// A single entry from the Fujifilm MakerNote IFD.
+#[derive(Debug, Clone)]
+pub struct MakerNoteEntry {
+    pub tag: u16,
+    pub data_type: u16,
+    pub count: u32,
+    // The raw value bytes, already resolved from the offset when they don't
+    // fit in the entry itself.
+    pub value: Vec<u8>,
+}
+
+impl MakerNoteEntry {
+    /// The name ExifTool uses for this tag, if we know it.
+    pub fn name(&self) -> Option<&'static str> {
+        tag_name(self.tag)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.data_type {
+            1 => "BYTE",
+            2 => "ASCII",
+            3 => "SHORT",
+            4 => "LONG",
+            5 => "RATIONAL",
+            6 => "SBYTE",
+            7 => "UNDEFINED",
+            8 => "SSHORT",
+            9 => "SLONG",
+            10 => "SRATIONAL",
+            11 => "FLOAT",
+            12 => "DOUBLE",
+            _ => "UNKNOWN",
+        }
+    }
+
+    pub fn u16(&self) -> Option<u16> {
+        let bytes = self.value.get(0..2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&self) -> Option<u32> {
+        self.i32_at(0).map(|n| n as u32)
+    }
+
+    pub fn i32(&self) -> Option<i32> {
+        self.i32_at(0)
+    }
+
+    pub fn i32_at(&self, index: usize) -> Option<i32> {
+        let bytes = self.value.get(index * 4..index * 4 + 4)?;
+        Some(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Renders the value according to its type: strings for ASCII,
+    /// space-separated numbers for the integer and rational types and hex
+    /// bytes for everything else.
+    pub fn value_string(&self) -> String {
+        let v = &self.value;
+        match self.data_type {
+            2 => String::from_utf8_lossy(v)
+                .trim_end_matches('\0')
+                .trim()
+                .to_string(),
+            1 => join(v.iter()),
+            6 => join(v.iter().map(|b| *b as i8)),
+            3 => join(v.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]))),
+            8 => join(v.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]]))),
+            4 => join(
+                v.chunks_exact(4)
+                    .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]])),
+            ),
+            9 => join(
+                v.chunks_exact(4)
+                    .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]])),
+            ),
+            5 => join(v.chunks_exact(8).map(|c| {
+                let n = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+                let d = u32::from_le_bytes([c[4], c[5], c[6], c[7]]);
+                format!("{}/{}", n, d)
+            })),
+            10 => join(v.chunks_exact(8).map(|c| {
+                let n = i32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+                let d = i32::from_le_bytes([c[4], c[5], c[6], c[7]]);
+                format!("{}/{}", n, d)
+            })),
+            _ => v
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+impl fmt::Display for MakerNoteEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "0x{:04x} {:<28} {:<9} {:>4}  {}",
+            self.tag,
+            self.name().unwrap_or("-"),
+            self.type_name(),
+            self.count,
+            self.value_string()
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for MakerNoteEntry {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("MakerNoteEntry", 5)?;
+        state.serialize_field("tag", &self.tag)?;
+        state.serialize_field("name", &self.name())?;
+        state.serialize_field("type", self.type_name())?;
+        state.serialize_field("count", &self.count)?;
+        state.serialize_field("value", &self.value_string())?;
+        state.end()
+    }
+}
+
+fn join<T: fmt::Display>(values: impl Iterator<Item = T>) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+fn type_size(data_type: u16) -> usize {
+    match data_type {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+fn truncated() -> FilmError {
+    FilmError::UnexpectedValue("MakerNote is truncated.".to_string())
+}
+
+// `len` bytes at `offset`.  Offsets come from the file, so the end is checked
+// for overflow: on 32-bit targets an offset near u32::MAX wraps.
+fn slice(data: &[u8], offset: usize, len: usize) -> Result<&[u8], FilmError> {
+    let end = offset.checked_add(len).ok_or_else(truncated)?;
+    data.get(offset..end).ok_or_else(truncated)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, FilmError> {
+    let bytes = slice(data, offset, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, FilmError> {
+    let bytes = slice(data, offset, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Walks the MakerNote IFD and returns every entry in the order the camera
+/// wrote them.
+pub fn read_entries(data: &[u8]) -> Result<Vec<MakerNoteEntry>, FilmError> {
+    match data.get(0..HEADER.len()) {
+        Some(header) if header == HEADER.as_bytes() => {}
+        _ => return Err(FilmError::NotAFujifilmFile),
+    }
+
+    let mut offset = read_u32(data, HEADER.len())? as usize;
+    let count = read_u16(data, offset)?;
+    // read_u16 checked that this is in bounds.
+    offset += 2;
+
+    let mut entries = Vec::with_capacity(count as usize);
+
+    for _ in 0..count {
+        // The entry itself is in bounds, so offsets within it don't wrap.
+        let entry = slice(data, offset, 12)?;
+        let tag = u16::from_le_bytes([entry[0], entry[1]]);
+        let data_type = u16::from_le_bytes([entry[2], entry[3]]);
+        let count = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+
+        let size = type_size(data_type).saturating_mul(count as usize);
+        let value = if size <= 4 {
+            &entry[8..8 + size]
+        } else {
+            let value_offset = read_u32(entry, 8)? as usize;
+            slice(data, value_offset, size)?
+        };
+
+        entries.push(MakerNoteEntry {
+            tag,
+            data_type,
+            count,
+            value: value.to_vec(),
+        });
+
+        offset += 12;
+    }
+
+    Ok(entries)
+}
+
+/// Returns the ExifTool name of a Fujifilm MakerNote tag.
+pub fn tag_name(tag: u16) -> Option<&'static str> {
+    let name = match tag {
+        0x0000 => "Version",
+        0x0010 => "InternalSerialNumber",
+        0x1000 => "Quality",
+        0x1001 => "Sharpness",
+        0x1002 => "WhiteBalance",
+        0x1003 => "Saturation",
+        0x1004 => "Contrast",
+        0x1005 => "ColorTemperature",
+        0x1006 => "Contrast",
+        0x100a => "WhiteBalanceFineTune",
+        0x100b => "NoiseReduction",
+        0x100e => "HighISONoiseReduction",
+        0x100f => "Clarity",
+        0x1010 => "FujiFlashMode",
+        0x1011 => "FlashExposureComp",
+        0x1020 => "Macro",
+        0x1021 => "FocusMode",
+        0x1022 => "AFMode",
+        0x1023 => "FocusPixel",
+        0x102b => "PrioritySettings",
+        0x102d => "FocusSettings",
+        0x102e => "AFCSettings",
+        0x1030 => "SlowSync",
+        0x1031 => "PictureMode",
+        0x1032 => "ExposureCount",
+        0x1033 => "EXRAuto",
+        0x1034 => "EXRMode",
+        0x1040 => "ShadowTone",
+        0x1041 => "HighlightTone",
+        0x1044 => "DigitalZoom",
+        0x1045 => "LensModulationOptimizer",
+        0x1047 => "GrainEffectRoughness",
+        0x1048 => "ColorChromeEffect",
+        0x1049 => "BWAdjustment",
+        0x104b => "BWMagentaGreen",
+        0x104c => "GrainEffectSize",
+        0x104d => "CropMode",
+        0x104e => "ColorChromeFXBlue",
+        0x1050 => "ShutterType",
+        0x1100 => "AutoBracketing",
+        0x1101 => "SequenceNumber",
+        0x1103 => "DriveSettings",
+        0x1105 => "PixelShiftShots",
+        0x1106 => "PixelShiftOffset",
+        0x1153 => "PanoramaAngle",
+        0x1154 => "PanoramaDirection",
+        0x1201 => "AdvancedFilter",
+        0x1210 => "ColorMode",
+        0x1300 => "BlurWarning",
+        0x1301 => "FocusWarning",
+        0x1302 => "ExposureWarning",
+        0x1304 => "GEImageSize",
+        0x1400 => "DynamicRange",
+        0x1401 => "FilmMode",
+        0x1402 => "DynamicRangeSetting",
+        0x1403 => "DevelopmentDynamicRange",
+        0x1404 => "MinFocalLength",
+        0x1405 => "MaxFocalLength",
+        0x1406 => "MaxApertureAtMinFocal",
+        0x1407 => "MaxApertureAtMaxFocal",
+        0x140b => "AutoDynamicRange",
+        0x1422 => "ImageStabilization",
+        0x1425 => "SceneRecognition",
+        0x1431 => "Rating",
+        0x1436 => "ImageGeneration",
+        0x1438 => "ImageCount",
+        0x1443 => "DRangePriority",
+        0x1444 => "DRangePriorityAuto",
+        0x1445 => "DRangePriorityFixed",
+        0x1446 => "FlickerReduction",
+        0x1447 => "FujiModel",
+        0x1448 => "FujiModel2",
+        0x144d => "RollAngle",
+        0x3803 => "VideoRecordingMode",
+        0x3804 => "PeripheralLighting",
+        0x3806 => "VideoCompression",
+        0x3820 => "FrameRate",
+        0x3821 => "FrameWidth",
+        0x3822 => "FrameHeight",
+        0x3824 => "FullHDHighSpeedRec",
+        0x4005 => "FaceElementSelected",
+        0x4100 => "FacesDetected",
+        0x4103 => "FacePositions",
+        0x4200 => "NumFaceElements",
+        0x4201 => "FaceElementTypes",
+        0x4203 => "FaceElementPositions",
+        0x4282 => "FaceRecInfo",
+        0x8000 => "FileSource",
+        0x8002 => "OrderNumber",
+        0x8003 => "FrameNumber",
+        0xb211 => "Parallax",
+        _ => return None,
+    };
+    Some(name)
+}
@@ -0,0 +1,290 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Multi-Picture Format (CIPA DC-007): MPO files from the 3D W-series and
+// JPEGs with embedded previews are several complete JPEGs in a row.  The
+// first one has an APP2 "MPF" segment holding a little TIFF structure whose
+// MP Entry tag lists the size and offset of every image.  Offsets are counted
+// from the start of that TIFF structure, except for the first image, which is
+// always at 0.
+
+use crate::container::{read_exif, settings_from_exif};
+use crate::jpeg::Jpeg;
+use crate::{FilmError, FujifilmSettings};
+use std::path::Path;
+
+const APP2: u8 = 0xe2;
+const MPF_HEADER: &[u8] = b"MPF\0";
+const MP_ENTRY: u16 = 0xb002;
+const MP_ENTRY_SIZE: usize = 16;
+
+/// One of the images in a multi-picture file.
+#[derive(Debug)]
+pub struct EmbeddedImage {
+    // 1 for the primary image.
+    pub index: usize,
+    pub kind: &'static str,
+    // Previews often have no MakerNote, so each image can fail on its own.
+    pub settings: Result<FujifilmSettings, FilmError>,
+}
+
+fn kind_name(attribute: u32) -> &'static str {
+    match attribute & 0xffffff {
+        0x000000 => "Baseline Primary Image",
+        0x010001 => "Large Thumbnail (VGA)",
+        0x010002 => "Large Thumbnail (Full HD)",
+        0x020001 => "Multi-Frame Panorama",
+        0x020002 => "Multi-Frame Disparity",
+        0x020003 => "Multi-Frame Multi-Angle",
+        0x030000 => "Baseline MP Primary Image",
+        _ => "Unknown",
+    }
+}
+
+fn invalid(message: &str) -> FilmError {
+    FilmError::InvalidJpeg(format!("MPF: {}", message))
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+// Offsets come from the file, so adding to them can overflow on 32-bit
+// targets.
+fn add(offset: usize, len: usize) -> Result<usize, FilmError> {
+    offset.checked_add(len).ok_or_else(|| invalid("truncated"))
+}
+
+impl Tiff<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8], FilmError> {
+        self.data
+            .get(offset..add(offset, len)?)
+            .ok_or_else(|| invalid("truncated"))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, FilmError> {
+        let b = self.bytes(offset, 2)?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, FilmError> {
+        let b = self.bytes(offset, 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+}
+
+// (attribute, file offset, size) for every image.
+fn image_ranges(mpf: &[u8], base: usize) -> Result<Vec<(u32, usize, usize)>, FilmError> {
+    let big_endian = match mpf.get(0..4) {
+        Some(b"MM\0*") => true,
+        Some(b"II*\0") => false,
+        _ => return Err(invalid("bad byte order mark")),
+    };
+    let tiff = Tiff {
+        data: mpf,
+        big_endian,
+    };
+
+    let ifd = tiff.u32(4)? as usize;
+    let count = tiff.u16(ifd)? as usize;
+    for i in 0..count {
+        let entry = add(ifd, 2 + i * 12)?;
+        if tiff.u16(entry)? != MP_ENTRY {
+            continue;
+        }
+
+        let images = tiff.u32(add(entry, 4)?)? as usize / MP_ENTRY_SIZE;
+        let values = tiff.u32(add(entry, 8)?)? as usize;
+        return (0..images)
+            .map(|n| {
+                let value = add(values, n * MP_ENTRY_SIZE)?;
+                let attribute = tiff.u32(value)?;
+                let size = tiff.u32(add(value, 4)?)? as usize;
+                let offset = match tiff.u32(add(value, 8)?)? as usize {
+                    0 => 0,
+                    offset => add(base, offset)?,
+                };
+                Ok((attribute, offset, size))
+            })
+            .collect();
+    }
+
+    Err(invalid("no MP Entry tag"))
+}
+
+/// Settings for every image in the file.  A plain JPEG is a single image.
+pub fn get_embedded_settings(path: &Path) -> Result<Vec<EmbeddedImage>, FilmError> {
+    embedded_settings(&std::fs::read(path)?)
+}
+
+fn embedded_settings(data: &[u8]) -> Result<Vec<EmbeddedImage>, FilmError> {
+    let jpeg = Jpeg::parse(data)?;
+    let mpf = jpeg
+        .segments
+        .iter()
+        .find(|s| s.marker == APP2 && s.data.starts_with(MPF_HEADER));
+
+    let settings = |image: &[u8]| {
+        read_exif(&mut std::io::Cursor::new(image)).and_then(|exif| settings_from_exif(&exif))
+    };
+
+    let Some(mpf) = mpf else {
+        return Ok(vec![EmbeddedImage {
+            index: 1,
+            kind: kind_name(0),
+            settings: settings(data),
+        }]);
+    };
+
+    // Where the TIFF structure starts in the file.
+    let base = mpf.data.as_ptr() as usize - data.as_ptr() as usize + MPF_HEADER.len();
+
+    Ok(image_ranges(&mpf.data[MPF_HEADER.len()..], base)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, (attribute, offset, size))| EmbeddedImage {
+            index: i + 1,
+            kind: kind_name(attribute),
+            settings: offset
+                .checked_add(size)
+                .and_then(|end| data.get(offset..end))
+                .ok_or_else(|| invalid("image outside of the file"))
+                .and_then(settings),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, FilmMode};
+
+    // Puts an MPF segment listing all of `images` into the first one and
+    // appends the rest.
+    fn mpo(images: &[Vec<u8>]) -> Vec<u8> {
+        let ifd_len = 2 + 12 + 4;
+        let entries_len = MP_ENTRY_SIZE * images.len();
+        let app2_len = 4 + MPF_HEADER.len() + 8 + ifd_len + entries_len;
+
+        // After SOI and the APP1 segment.
+        let first = &images[0];
+        let insert_at = 4 + u16::from_be_bytes([first[4], first[5]]) as usize;
+        let base = insert_at + 4 + MPF_HEADER.len();
+
+        let mut offset = first.len() + app2_len;
+        let mut entries = vec![];
+        for (i, image) in images.iter().enumerate() {
+            let (attribute, size, start) = match i {
+                0 => (0x20020002u32, first.len() + app2_len, 0),
+                _ => (0x00020002, image.len(), offset - base),
+            };
+            if i > 0 {
+                offset += image.len();
+            }
+            entries.extend_from_slice(&attribute.to_le_bytes());
+            entries.extend_from_slice(&(size as u32).to_le_bytes());
+            entries.extend_from_slice(&(start as u32).to_le_bytes());
+            entries.extend_from_slice(&[0; 4]);
+        }
+
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&MP_ENTRY.to_le_bytes());
+        tiff.extend_from_slice(&7u16.to_le_bytes());
+        tiff.extend_from_slice(&(entries_len as u32).to_le_bytes());
+        tiff.extend_from_slice(&((8 + ifd_len) as u32).to_le_bytes());
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(&entries);
+
+        let mut out = first[..insert_at].to_vec();
+        out.extend_from_slice(&[0xff, APP2]);
+        out.extend_from_slice(&((app2_len - 2) as u16).to_be_bytes());
+        out.extend_from_slice(MPF_HEADER);
+        out.extend_from_slice(&tiff);
+        out.extend_from_slice(&first[insert_at..]);
+        for image in &images[1..] {
+            out.extend_from_slice(image);
+        }
+        out
+    }
+
+    #[test]
+    fn reads_every_image() {
+        let mut left = FujifilmSettings::new();
+        left.film_mode = FilmMode::Velvia;
+        let mut right = FujifilmSettings::new();
+        right.film_mode = FilmMode::Astia;
+
+        let data = mpo(&[
+            fixtures::jpeg(&left),
+            fixtures::jpeg(&right),
+            fixtures::jpeg_with_exif(&[]),
+        ]);
+        let images = embedded_settings(&data).unwrap();
+
+        assert_eq!(images.len(), 3);
+        assert_eq!(images[0].kind, "Multi-Frame Disparity");
+        assert_eq!(images[0].settings.as_ref().unwrap(), &left);
+        assert_eq!(images[1].settings.as_ref().unwrap(), &right);
+        assert!(matches!(
+            images[2].settings,
+            Err(FilmError::NotAFujifilmFile)
+        ));
+    }
+
+    #[test]
+    fn rejects_offsets_past_the_end() {
+        let tiff = |ifd: u32, offset: u32| {
+            let mut tiff = b"II*\0".to_vec();
+            tiff.extend_from_slice(&ifd.to_le_bytes());
+            tiff.extend_from_slice(&1u16.to_le_bytes());
+            tiff.extend_from_slice(&MP_ENTRY.to_le_bytes());
+            tiff.extend_from_slice(&7u16.to_le_bytes());
+            tiff.extend_from_slice(&(MP_ENTRY_SIZE as u32).to_le_bytes());
+            tiff.extend_from_slice(&26u32.to_le_bytes());
+            tiff.extend_from_slice(&0u32.to_le_bytes());
+            tiff.extend_from_slice(&0x00020002u32.to_le_bytes());
+            tiff.extend_from_slice(&u32::MAX.to_le_bytes());
+            tiff.extend_from_slice(&offset.to_le_bytes());
+            tiff.extend_from_slice(&[0; 4]);
+            tiff
+        };
+
+        assert!(image_ranges(&tiff(8, 1), 0).is_ok());
+        assert!(image_ranges(&tiff(u32::MAX, 1), 0).is_err());
+        assert!(image_ranges(&tiff(8, 1), usize::MAX).is_err());
+    }
+
+    #[test]
+    fn plain_jpeg_is_one_image() {
+        let settings = FujifilmSettings::new();
+        let images = embedded_settings(&fixtures::jpeg(&settings)).unwrap();
+
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].settings.as_ref().unwrap(), &settings);
+    }
+}
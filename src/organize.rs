@@ -0,0 +1,444 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Sorts photos into a directory layout built from their settings, e.g.
+// `{film_mode}/{recipe}/{date}_{name}` ->
+// `Classic Chrome/Street Chrome/2024-05-01_DSCF0001.JPG`.
+
+use crate::container::{exif_from_reader, open, settings_from_exif};
+use crate::{find_recipe, FilmError, Recipe};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_TEMPLATE: &str = "{film_mode}/{recipe}/{date}_{name}";
+
+// Used for `{recipe}` when nothing matches and for `{date}` when the camera
+// didn't record one.
+const NO_RECIPE: &str = "No Recipe";
+const NO_DATE: &str = "Unknown Date";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TransferMode {
+    #[default]
+    Move,
+    Copy,
+    Hardlink,
+}
+
+/// What to do when the target file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Collision {
+    #[default]
+    Skip,
+    // Append -1, -2, ... to the file name until it's free.
+    Rename,
+    // Replace files that were there before the run.  Two files of one run
+    // that want the same name are renamed instead, so neither is lost.
+    Overwrite,
+}
+
+pub struct OrganizeOptions {
+    pub template: String,
+    pub destination: PathBuf,
+    pub mode: TransferMode,
+    pub collision: Collision,
+    pub dry_run: bool,
+}
+
+/// Where a file went.  `target` is `None` when the file was skipped because
+/// of a collision.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Placement {
+    pub source: PathBuf,
+    pub target: Option<PathBuf>,
+}
+
+// "2024:05:01 12:34:56" -> "2024-05-01"
+fn date_taken(exif: &exif::Exif) -> Option<String> {
+    [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+        .iter()
+        .find_map(|tag| exif.get_field(*tag, exif::In::PRIMARY))
+        .and_then(|field| match field.value {
+            exif::Value::Ascii(ref v) => v.first().cloned(),
+            _ => None,
+        })
+        .and_then(|v| {
+            let date = String::from_utf8_lossy(v.get(0..10)?).replace(':', "-");
+            Some(date)
+        })
+}
+
+// Values become path components, so they can't contain separators, and "",
+// "." and ".." would collapse the layout or climb out of the destination.
+fn sanitize(value: &str) -> String {
+    match value.trim() {
+        "" | "." | ".." => "_".to_string(),
+        _ => value.replace(['/', '\\'], "-"),
+    }
+}
+
+/// Fills in the template.  The extension of the original file is kept.
+pub fn render_template(template: &str, values: &[(&str, String)]) -> Result<String, FilmError> {
+    let mut out = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| {
+            FilmError::InvalidTemplate(format!("Unclosed placeholder in {}", template))
+        })?;
+        let key = &rest[start + 1..start + end];
+        let value = values
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+            .ok_or_else(|| {
+                FilmError::InvalidTemplate(format!("Unknown placeholder {{{}}}", key))
+            })?;
+        out.push_str(&sanitize(value));
+        rest = &rest[start + end + 1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn target_for(
+    path: &Path,
+    options: &OrganizeOptions,
+    recipes: &[Recipe],
+) -> Result<PathBuf, FilmError> {
+    let exif = exif_from_reader(&mut open(path)?)?;
+    let settings = settings_from_exif(&exif)?;
+
+    let recipe = find_recipe(&settings, recipes)
+        .map(|r| r.name.clone())
+        .unwrap_or_else(|| NO_RECIPE.to_string());
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let values = [
+        ("film_mode", settings.film_mode().to_string()),
+        ("recipe", recipe),
+        (
+            "date",
+            date_taken(&exif).unwrap_or_else(|| NO_DATE.to_string()),
+        ),
+        ("name", name),
+    ];
+
+    let mut target = options
+        .destination
+        .join(render_template(&options.template, &values)?);
+    if let Some(extension) = path.extension() {
+        let mut file_name = target.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".");
+        file_name.push(extension);
+        target.set_file_name(file_name);
+    }
+    Ok(target)
+}
+
+// A file that was already sorted into this destination.
+fn is_in_place(source: &Path, target: &Path) -> bool {
+    match (std::fs::canonicalize(source), std::fs::canonicalize(target)) {
+        (Ok(source), Ok(target)) => source == target,
+        _ => false,
+    }
+}
+
+fn is_taken(target: &Path, planned: &HashSet<PathBuf>) -> bool {
+    target.exists() || planned.contains(target)
+}
+
+// photo.jpg -> photo-1.jpg, photo-2.jpg, ...
+fn free_name(target: &Path, planned: &HashSet<PathBuf>) -> PathBuf {
+    let stem = target
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = target.extension().map(|e| e.to_string_lossy().into_owned());
+
+    (1..)
+        .map(|n| {
+            let name = match &extension {
+                Some(extension) => format!("{}-{}.{}", stem, n, extension),
+                None => format!("{}-{}", stem, n),
+            };
+            target.with_file_name(name)
+        })
+        .find(|candidate| !is_taken(candidate, planned))
+        .unwrap_or_else(|| target.to_path_buf())
+}
+
+fn transfer(source: &Path, target: &Path, options: &OrganizeOptions) -> Result<(), FilmError> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if options.collision == Collision::Overwrite && options.mode != TransferMode::Move {
+        match std::fs::remove_file(target) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    match options.mode {
+        TransferMode::Move => match std::fs::rename(source, target) {
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                std::fs::copy(source, target)?;
+                std::fs::remove_file(source)?;
+            }
+            result => result?,
+        },
+        TransferMode::Copy => {
+            std::fs::copy(source, target)?;
+        }
+        TransferMode::Hardlink => std::fs::hard_link(source, target)?,
+    }
+
+    Ok(())
+}
+
+/// Moves, copies or links every file into the layout described by
+/// `options.template`.  Files that fail are reported individually and don't
+/// stop the rest.
+pub fn organize(
+    files: &[PathBuf],
+    options: &OrganizeOptions,
+    recipes: &[Recipe],
+) -> Vec<Result<Placement, FilmError>> {
+    // Targets claimed earlier in this run.  In a dry run nothing is written,
+    // so this is the only way to spot two files competing for one name.
+    let mut planned = HashSet::new();
+
+    files
+        .iter()
+        .map(|source| {
+            let mut target = target_for(source, options, recipes)?;
+
+            // Overwriting a file with itself would delete the only copy.
+            if is_in_place(source, &target) {
+                planned.insert(target.clone());
+                return Ok(Placement {
+                    source: source.clone(),
+                    target: Some(target),
+                });
+            }
+
+            if is_taken(&target, &planned) {
+                match options.collision {
+                    Collision::Skip => {
+                        return Ok(Placement {
+                            source: source.clone(),
+                            target: None,
+                        })
+                    }
+                    Collision::Rename => target = free_name(&target, &planned),
+                    Collision::Overwrite if planned.contains(&target) => {
+                        target = free_name(&target, &planned)
+                    }
+                    Collision::Overwrite => {}
+                }
+            }
+
+            if !options.dry_run {
+                transfer(source, &target, options)?;
+            }
+            planned.insert(target.clone());
+
+            Ok(Placement {
+                source: source.clone(),
+                target: Some(target),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("film-detect-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn photo(dir: &Path, name: &str) -> PathBuf {
+        let maker_note =
+            fixtures::maker_note(&fixtures::settings_entries(&crate::FujifilmSettings::new()));
+        let data = fixtures::jpeg_with_exif(&[
+            fixtures::ascii(0x9003, "2024:05:01 12:34:56"),
+            fixtures::entry(
+                crate::container::MAKER_NOTES_TAG,
+                7,
+                maker_note.len() as u32,
+                maker_note,
+            ),
+        ]);
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn options(destination: &Path, collision: Collision) -> OrganizeOptions {
+        OrganizeOptions {
+            template: DEFAULT_TEMPLATE.to_string(),
+            destination: destination.to_path_buf(),
+            mode: TransferMode::Copy,
+            collision,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn renders_template() {
+        let values = [
+            ("film_mode", "Classic Chrome".to_string()),
+            ("recipe", "A/B".to_string()),
+        ];
+        assert_eq!(
+            render_template("{film_mode}/{recipe}", &values).unwrap(),
+            "Classic Chrome/A-B"
+        );
+        assert!(matches!(
+            render_template("{iso}", &values),
+            Err(FilmError::InvalidTemplate(_))
+        ));
+        assert!(matches!(
+            render_template("{film_mode", &values),
+            Err(FilmError::InvalidTemplate(_))
+        ));
+
+        let values = [("recipe", "..".to_string()), ("name", " ".to_string())];
+        assert_eq!(render_template("{recipe}/{name}", &values).unwrap(), "_/_");
+    }
+
+    #[test]
+    fn organizes_files() {
+        let dir = temp_dir("organize");
+        let source = photo(&dir, "DSCF0001.JPG");
+        let destination = dir.join("sorted");
+
+        let placements = organize(
+            std::slice::from_ref(&source),
+            &options(&destination, Collision::Skip),
+            &[],
+        );
+        let target = placements[0].as_ref().unwrap().target.clone().unwrap();
+
+        assert_eq!(
+            target,
+            destination.join("None/No Recipe/2024-05-01_DSCF0001.JPG")
+        );
+        assert!(target.exists());
+        assert!(source.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn leaves_sorted_files_in_place() {
+        let dir = temp_dir("in-place");
+        let destination = dir.join("sorted");
+        let sorted = destination.join("None/No Recipe");
+        std::fs::create_dir_all(&sorted).unwrap();
+        let source = photo(&sorted, "2024-05-01_DSCF0001.JPG");
+        let mut options = options(&destination, Collision::Overwrite);
+        options.template = "{film_mode}/{recipe}/{name}".to_string();
+
+        for mode in [TransferMode::Copy, TransferMode::Hardlink] {
+            options.mode = mode;
+            let placements = organize(std::slice::from_ref(&source), &options, &[]);
+            assert_eq!(
+                placements[0].as_ref().unwrap().target.as_ref(),
+                Some(&source)
+            );
+            assert!(source.exists());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn handles_collisions() {
+        let dir = temp_dir("collisions");
+        let a = photo(&dir, "DSCF0001.JPG");
+        let b = photo(&dir, "DSCF0002.JPG");
+        let destination = dir.join("sorted");
+        let mut options = options(&destination, Collision::Skip);
+        options.template = "{film_mode}/{date}".to_string();
+        options.dry_run = true;
+
+        let skipped = organize(&[a.clone(), b.clone()], &options, &[]);
+        assert!(skipped[0].as_ref().unwrap().target.is_some());
+        assert!(skipped[1].as_ref().unwrap().target.is_none());
+
+        options.collision = Collision::Rename;
+        let renamed = organize(&[a, b], &options, &[]);
+        assert_eq!(
+            renamed[1].as_ref().unwrap().target,
+            Some(destination.join("None/2024-05-01-1.JPG"))
+        );
+        // Dry runs don't touch the disk.
+        assert!(!destination.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn overwrite_keeps_files_of_the_same_run() {
+        let dir = temp_dir("overwrite");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        let a = photo(&dir.join("a"), "DSCF0001.JPG");
+        let b = photo(&dir.join("b"), "DSCF0001.JPG");
+        let data = std::fs::read(&a).unwrap();
+        let destination = dir.join("sorted");
+        let existing = destination.join("None/DSCF0001.JPG");
+        std::fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        std::fs::write(&existing, b"before the run").unwrap();
+
+        let mut options = options(&destination, Collision::Overwrite);
+        options.template = "{film_mode}/{name}".to_string();
+        options.mode = TransferMode::Move;
+        let placements = organize(&[a.clone(), b.clone()], &options, &[]);
+
+        // The file from before the run is replaced, the second photo of the
+        // run is not.
+        assert_eq!(
+            placements[0].as_ref().unwrap().target,
+            Some(existing.clone())
+        );
+        let renamed = destination.join("None/DSCF0001-1.JPG");
+        assert_eq!(
+            placements[1].as_ref().unwrap().target,
+            Some(renamed.clone())
+        );
+        assert_eq!(std::fs::read(&existing).unwrap(), data);
+        assert_eq!(std::fs::read(&renamed).unwrap(), data);
+        assert!(!a.exists() && !b.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The Python module, built by maturin with the "python" feature:
+//
+//     >>> import film_detect
+//     >>> recipes = film_detect.load_recipes("recipes/")
+//     >>> film_detect.get_fujifilm_settings("DSCF0001.JPG", recipes)["recipe"]
+//     'Kodak Portra 400'
+//
+// Settings come back as the dicts `--json` prints; film_detect.pyi describes
+// their keys and types.
+
+use crate::{FilmError, FujifilmSettings, Recipe};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use std::path::PathBuf;
+
+create_exception!(
+    film_detect,
+    FilmDetectError,
+    PyException,
+    "The photo has no Fujifilm settings, or they can't be read."
+);
+
+fn py_error(error: FilmError) -> PyErr {
+    match error {
+        // FileNotFoundError and friends.
+        FilmError::IO(e) => e.into(),
+        e => FilmDetectError::new_err(e.to_string()),
+    }
+}
+
+/// The recipes in a film simulation directory, loaded once and matched
+/// against any number of photos.
+#[pyclass(frozen)]
+struct Recipes {
+    recipes: Vec<Recipe>,
+}
+
+#[pymethods]
+impl Recipes {
+    /// The names of the recipes, sorted.
+    #[getter]
+    fn names(&self) -> Vec<String> {
+        self.recipes.iter().map(|r| r.name.clone()).collect()
+    }
+
+    fn __len__(&self) -> usize {
+        self.recipes.len()
+    }
+}
+
+// The settings as a dict, with the matching recipe's name under "recipe".
+fn settings_dict<'py>(
+    py: Python<'py>,
+    settings: Result<FujifilmSettings, FilmError>,
+    recipes: Option<PyRef<'py, Recipes>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let settings = settings.map_err(py_error)?;
+    let mut output = serde_json::json!(settings);
+    if let Some(recipes) = recipes {
+        output["recipe"] =
+            serde_json::json!(crate::find_recipe(&settings, &recipes.recipes).map(|r| &r.name));
+    }
+    py.import("json")?
+        .call_method1("loads", (output.to_string(),))
+}
+
+/// Loads every recipe and X RAW STUDIO profile in a film simulation
+/// directory.
+#[pyfunction]
+fn load_recipes(py: Python<'_>, dir: PathBuf) -> PyResult<Recipes> {
+    let recipes = py.detach(|| crate::load_recipes(&dir)).map_err(py_error)?;
+    Ok(Recipes { recipes })
+}
+
+/// Reads the settings of the photo at `path`.
+#[pyfunction]
+#[pyo3(signature = (path, recipes=None))]
+fn get_fujifilm_settings<'py>(
+    py: Python<'py>,
+    path: PathBuf,
+    recipes: Option<PyRef<'py, Recipes>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let settings = py.detach(|| crate::get_fujifilm_settings(&path));
+    settings_dict(py, settings, recipes)
+}
+
+/// Reads the settings of a photo in memory.
+#[pyfunction]
+#[pyo3(signature = (data, recipes=None))]
+fn get_fujifilm_settings_from_bytes<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    recipes: Option<PyRef<'py, Recipes>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let settings = py.detach(|| crate::get_fujifilm_settings_from_bytes(data));
+    settings_dict(py, settings, recipes)
+}
+
+#[pymodule]
+fn film_detect(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("FilmDetectError", m.py().get_type::<FilmDetectError>())?;
+    m.add_class::<Recipes>()?;
+    m.add_function(wrap_pyfunction!(load_recipes, m)?)?;
+    m.add_function(wrap_pyfunction!(get_fujifilm_settings, m)?)?;
+    m.add_function(wrap_pyfunction!(get_fujifilm_settings_from_bytes, m)?)?;
+    Ok(())
+}
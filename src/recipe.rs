@@ -0,0 +1,111 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(all(feature = "serde", feature = "xmp"))]
+use crate::read_profile;
+#[cfg(all(feature = "std", feature = "serde"))]
+use crate::FilmError;
+use crate::FujifilmSettings;
+use alloc::string::String;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(all(feature = "std", feature = "serde"))]
+use std::path::Path;
+
+/// A named set of settings, e.g. "Kodak Portra 400".
+///
+/// Recipes live in a film simulation directory, one JSON file per recipe:
+///
+/// ```json
+/// {"name": "Kodak Portra 400", "settings": {"film_mode": "ClassicChrome", ...}}
+/// ```
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Recipe {
+    pub name: String,
+    pub settings: FujifilmSettings,
+}
+
+/// Loads every `*.json` recipe and X RAW STUDIO conversion profile
+/// (`*.fp1`, `*.fp2`, `*.fp3`) in `dir`, sorted by name.  Profiles need the
+/// `xmp` feature and are skipped without it.
+#[cfg(all(feature = "std", feature = "serde"))]
+pub fn load_recipes(dir: &Path) -> Result<Vec<Recipe>, FilmError> {
+    let mut recipes = vec![];
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        let recipe: Recipe = match extension.as_deref() {
+            Some("json") => {
+                let data = std::fs::read_to_string(&path)?;
+                serde_json::from_str(&data)
+                    .map_err(|e| FilmError::InvalidRecipe(format!("{}: {}", path.display(), e)))?
+            }
+            #[cfg(feature = "xmp")]
+            Some("fp1" | "fp2" | "fp3") => read_profile(&path).map_err(|e| match e {
+                FilmError::InvalidProfile(message) => {
+                    FilmError::InvalidRecipe(format!("{}: {}", path.display(), message))
+                }
+                e => e,
+            })?,
+            _ => continue,
+        };
+        recipes.push(recipe);
+    }
+
+    recipes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(recipes)
+}
+
+// The photo has to have the recipe's settings.  A recipe that says DR200 also
+// matches a photo shot on Auto that got DR200, and the color temperature only
+// counts when the recipe gives one.  How the photo was exposed, which body took
+// it and whether it was part of a burst don't count.  An advanced filter,
+// even one we don't know, changes the look too much for any recipe to apply.
+fn matches(settings: &FujifilmSettings, recipe: &FujifilmSettings) -> bool {
+    if !settings.advanced_filter.is_off() {
+        return false;
+    }
+
+    let mut settings = settings.clone();
+    settings.shot_info = recipe.shot_info.clone();
+    settings.body = recipe.body.clone();
+    settings.sequence = recipe.sequence;
+
+    if settings.applied_dynamic_range == Some(recipe.dynamic_range) {
+        settings.dynamic_range = recipe.dynamic_range;
+    }
+    if recipe.applied_dynamic_range.is_none() {
+        settings.applied_dynamic_range = None;
+    }
+    if recipe.color_temperature.is_none() {
+        settings.color_temperature = None;
+    }
+
+    settings == *recipe
+}
+
+/// Returns the first recipe whose settings the photo was shot with.
+pub fn find_recipe<'a>(settings: &FujifilmSettings, recipes: &'a [Recipe]) -> Option<&'a Recipe> {
+    recipes
+        .iter()
+        .find(|recipe| matches(settings, &recipe.settings))
+}
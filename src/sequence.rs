@@ -0,0 +1,238 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Brackets and bursts.  Every frame of a sequence carries its position in
+// tag 0x1101, starting at 1, so consecutive files with consecutive numbers
+// belong together.  A film simulation bracket is three frames from a single
+// exposure, each with a different film simulation; the camera flags it as
+// bracketing in tag 0x1100, which a burst doesn't have.
+
+use crate::{Choice, Choices};
+use core::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Grouping works on files, so it needs std.
+#[cfg(feature = "std")]
+use crate::FujifilmSettings;
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum AutoBracketing {
+    #[default]
+    Off,
+    On,
+    NoFlashAndFlash,
+    PixelShift,
+    /// A mode this version doesn't know.  Still a bracket.
+    Unknown,
+}
+
+impl Choices for AutoBracketing {
+    const NAME: &'static str = "auto bracketing";
+    const CHOICES: &'static [Choice<Self>] = &[
+        crate::label(Self::Off, Some(0), "Off", "Off"),
+        crate::label(Self::On, Some(1), "On", "On"),
+        crate::label(
+            Self::NoFlashAndFlash,
+            Some(2),
+            "NoFlashAndFlash",
+            "No Flash & Flash",
+        ),
+        crate::label(Self::PixelShift, Some(6), "PixelShift", "Pixel Shift"),
+        crate::label(Self::Unknown, None, "Unknown", "Unknown"),
+    ];
+}
+
+setting_traits!(AutoBracketing);
+
+/// Where a frame sits in a bracket or burst.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sequence {
+    // 1 for the first frame.
+    pub number: u16,
+    pub auto_bracketing: AutoBracketing,
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Frame {}", self.number)?;
+        if self.auto_bracketing != AutoBracketing::Off {
+            write!(f, ", auto bracketing {}", self.auto_bracketing)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum GroupKind {
+    Single,
+    FilmSimulationBracket,
+    Bracket,
+    Burst,
+}
+
+/// Photos that were shot as one bracket or burst, or a photo on its own.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct Group {
+    pub kind: GroupKind,
+    pub photos: Vec<(PathBuf, FujifilmSettings)>,
+}
+
+#[cfg(feature = "std")]
+impl Group {
+    fn kind_of(photos: &[(PathBuf, FujifilmSettings)]) -> GroupKind {
+        if photos.len() < 2 {
+            return GroupKind::Single;
+        }
+
+        let bracketing = photos.iter().all(|(_, s)| {
+            s.sequence
+                .is_some_and(|s| s.auto_bracketing != AutoBracketing::Off)
+        });
+        if !bracketing {
+            return GroupKind::Burst;
+        }
+
+        let modes: Vec<_> = photos.iter().map(|(_, s)| s.film_mode()).collect();
+        let distinct = modes
+            .iter()
+            .enumerate()
+            .all(|(i, mode)| !modes[..i].contains(mode));
+
+        if photos.len() == 3 && distinct {
+            GroupKind::FilmSimulationBracket
+        } else {
+            GroupKind::Bracket
+        }
+    }
+
+    /// "3-frame film sim bracket: Provia / Velvia / Classic Chrome"
+    pub fn summary(&self) -> String {
+        let modes: Vec<String> = self
+            .photos
+            .iter()
+            .map(|(_, s)| s.film_mode().to_string())
+            .collect();
+        let frames = self.photos.len();
+
+        match self.kind {
+            GroupKind::Single => modes[0].clone(),
+            GroupKind::FilmSimulationBracket => {
+                format!("{}-frame film sim bracket: {}", frames, modes.join(" / "))
+            }
+            GroupKind::Bracket => format!("{}-frame bracket: {}", frames, modes[0]),
+            GroupKind::Burst => format!("{}-frame burst: {}", frames, modes[0]),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn number(settings: &FujifilmSettings) -> Option<u16> {
+    settings.sequence.map(|s| s.number)
+}
+
+/// Groups the photos, in the order given, into brackets and bursts.  Files
+/// should be passed in the order the camera wrote them, i.e. sorted by name.
+#[cfg(feature = "std")]
+pub fn group_sequences(photos: Vec<(PathBuf, FujifilmSettings)>) -> Vec<Group> {
+    let mut groups: Vec<Vec<(PathBuf, FujifilmSettings)>> = vec![];
+
+    for photo in photos {
+        let continues = match (groups.last().and_then(|g| g.last()), number(&photo.1)) {
+            (Some((_, previous)), Some(n)) => n > 1 && number(previous) == Some(n - 1),
+            _ => false,
+        };
+
+        match groups.last_mut() {
+            Some(group) if continues => group.push(photo),
+            _ => groups.push(vec![photo]),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|photos| Group {
+            kind: Group::kind_of(&photos),
+            photos,
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::FilmMode;
+
+    fn photo(
+        name: &str,
+        film_mode: FilmMode,
+        number: u16,
+        auto_bracketing: AutoBracketing,
+    ) -> (PathBuf, FujifilmSettings) {
+        let mut settings = FujifilmSettings::new();
+        settings.film_mode = film_mode;
+        settings.sequence = (number > 0).then_some(Sequence {
+            number,
+            auto_bracketing,
+        });
+        (PathBuf::from(name), settings)
+    }
+
+    #[test]
+    fn groups_sequences() {
+        use AutoBracketing::{Off, On};
+        let groups = group_sequences(vec![
+            photo("1.JPG", FilmMode::Provia, 1, On),
+            photo("2.JPG", FilmMode::Velvia, 2, On),
+            photo("3.JPG", FilmMode::ClassicChrome, 3, On),
+            photo("4.JPG", FilmMode::Eterna, 0, Off),
+            photo("5.JPG", FilmMode::Acros, 1, Off),
+            photo("6.JPG", FilmMode::Acros, 2, Off),
+            photo("7.JPG", FilmMode::Acros, 1, Off),
+            // A burst where the film simulation changed mid-way.
+            photo("8.JPG", FilmMode::Provia, 1, Off),
+            photo("9.JPG", FilmMode::Velvia, 2, Off),
+            photo("10.JPG", FilmMode::Eterna, 3, Off),
+            // Two frames of a bracket aren't a film simulation bracket.
+            photo("11.JPG", FilmMode::Provia, 1, On),
+            photo("12.JPG", FilmMode::Velvia, 2, On),
+        ]);
+
+        let kinds: Vec<_> = groups.iter().map(|g| g.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                GroupKind::FilmSimulationBracket,
+                GroupKind::Single,
+                GroupKind::Burst,
+                GroupKind::Single,
+                GroupKind::Burst,
+                GroupKind::Bracket,
+            ]
+        );
+        assert_eq!(
+            groups[0].summary(),
+            "3-frame film sim bracket: Provia / Velvia / Classic Chrome"
+        );
+        assert_eq!(groups[2].summary(), "2-frame burst: Acros");
+    }
+}
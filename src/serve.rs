@@ -0,0 +1,318 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// A small HTTP API for tools that would rather not shell out:
+//
+//     GET  /settings?path=/mnt/archive/DSCF0001.JPG
+//     POST /settings    with the photo as the body, or as a multipart form
+//
+// Both answer with the JSON `--json` prints, or with {"error": ...} and a
+// status code that says whose fault it was.  GET only reads regular files
+// under the root directory it was given.  Requests are handled one at a time.
+
+use crate::{
+    find_recipe, get_fujifilm_settings, get_fujifilm_settings_from_bytes, FilmError, Recipe,
+    Redaction,
+};
+use serde_json::{json, Value};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tiny_http::{Header, Response, Server};
+
+// Comfortably more than the largest JPEG or HEIF a camera writes.
+const MAX_UPLOAD: u64 = 256 << 20;
+
+// 422 for photos we can't read settings from, 404 and 403 for paths that
+// aren't there or can't be opened, and 500 for the rest.
+fn status(error: &FilmError) -> u16 {
+    match error {
+        FilmError::NotAFujifilmFile
+        | FilmError::Exif(_)
+        | FilmError::UnexpectedValue(_)
+        | FilmError::InvalidJpeg(_) => 422,
+        FilmError::IO(e) if e.kind() == io::ErrorKind::NotFound => 404,
+        FilmError::IO(e) if e.kind() == io::ErrorKind::PermissionDenied => 403,
+        FilmError::IO(_)
+        | FilmError::InvalidRecipe(_)
+        | FilmError::Xmp(_)
+        | FilmError::InvalidTemplate(_)
+        | FilmError::InvalidProfile(_)
+        | FilmError::InvalidExifToolJson(_)
+        | FilmError::Catalog(_) => 500,
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// The first file in a multipart/form-data body, which is what an HTML form
+// or `curl -F photo=@DSCF0001.JPG` sends.
+fn multipart_file<'a>(body: &'a [u8], content_type: &str) -> Option<&'a [u8]> {
+    let boundary = content_type
+        .split(';')
+        .find_map(|p| p.trim().strip_prefix("boundary="))?
+        .trim_matches('"');
+    let delimiter = format!("\r\n--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    // The first boundary doesn't follow a line break.
+    let mut rest = &body[find(body, &delimiter[2..])? + delimiter.len() - 2..];
+    loop {
+        let headers_end = find(rest, b"\r\n\r\n")?;
+        let headers = String::from_utf8_lossy(&rest[..headers_end]).to_ascii_lowercase();
+        let content = &rest[headers_end + 4..];
+        let end = find(content, delimiter)?;
+        if headers.contains("filename=") {
+            return Some(&content[..end]);
+        }
+        rest = &content[end + delimiter.len()..];
+    }
+}
+
+// The decoded value of `name` in the URL's query string.
+fn query_param(url: &str, name: &str) -> Option<String> {
+    let (_, query) = url.split_once('?')?;
+    let value = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)?
+        .1;
+
+    let mut bytes = vec![];
+    let mut chars = value.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [chars.next()?, chars.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (status, json!({"error": message}))
+}
+
+// The file `path` names under `root`, relative to it or not.  Only regular
+// files: a FIFO or /dev/zero would block the server.  Paths outside the root
+// get the same answer as missing ones, so they can't be used to probe the
+// rest of the file system.  `root` is canonical.
+fn local_file(root: &Path, path: &str) -> Result<PathBuf, (u16, Value)> {
+    let not_found = || error(404, "no such file under the root");
+    let file = root.join(path).canonicalize().map_err(|_| not_found())?;
+    if !file.starts_with(root) {
+        return Err(not_found());
+    }
+    match std::fs::metadata(&file) {
+        Ok(metadata) if metadata.is_file() => Ok(file),
+        Ok(_) => Err(error(403, "not a regular file")),
+        Err(e) => {
+            let e = FilmError::from(e);
+            Err(error(status(&e), &e.to_string()))
+        }
+    }
+}
+
+// Answers one request.  `body` is empty for GET.
+fn respond(
+    method: &str,
+    url: &str,
+    content_type: Option<&str>,
+    body: &[u8],
+    recipes: &[Recipe],
+    redaction: Option<&Redaction>,
+    root: Option<&Path>,
+) -> (u16, Value) {
+    let route = url.split('?').next().unwrap_or_default();
+    if route != "/settings" {
+        return error(404, "not found");
+    }
+
+    let settings = match method {
+        "GET" => match (query_param(url, "path"), root) {
+            (Some(path), Some(root)) => match local_file(root, &path) {
+                Ok(file) => get_fujifilm_settings(&file),
+                Err(e) => return e,
+            },
+            (Some(_), None) => return error(403, "reading files on the server needs --root"),
+            (None, _) => return error(400, "missing path parameter"),
+        },
+        "POST" => {
+            let photo = match content_type {
+                Some(t) if t.starts_with("multipart/form-data") => match multipart_file(body, t) {
+                    Some(photo) => photo,
+                    None => return error(400, "no file in the form"),
+                },
+                _ => body,
+            };
+            get_fujifilm_settings_from_bytes(photo)
+        }
+        _ => return error(405, "method not allowed"),
+    };
+
+    match settings {
+        Ok(mut settings) => {
+            if let Some(redaction) = redaction {
+                settings.redact(redaction);
+            }
+            let mut output = json!(settings);
+            if let Some(recipe) = find_recipe(&settings, recipes) {
+                output["recipe"] = json!(recipe.name);
+            }
+            (200, output)
+        }
+        Err(e) => error(status(&e), &e.to_string()),
+    }
+}
+
+/// Serves the settings of photos over HTTP on `addr`, e.g. "127.0.0.1:8080",
+/// until the process is stopped.  `GET` requests can read photos under
+/// `root`; without it only uploads are answered.
+pub fn serve(
+    addr: &str,
+    recipes: &[Recipe],
+    redaction: Option<Redaction>,
+    root: Option<&Path>,
+) -> Result<(), FilmError> {
+    let root = root.map(Path::canonicalize).transpose()?;
+    let server = Server::http(addr).map_err(io::Error::other)?;
+    let json_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+
+    for mut request in server.incoming_requests() {
+        let method = request.method().as_str().to_string();
+        let content_type = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Content-Type"))
+            .map(|h| h.value.as_str().to_string());
+
+        let mut body = vec![];
+        let (code, value) = match request
+            .as_reader()
+            .take(MAX_UPLOAD + 1)
+            .read_to_end(&mut body)
+        {
+            Ok(_) if body.len() as u64 > MAX_UPLOAD => error(413, "upload too large"),
+            Ok(_) => respond(
+                &method,
+                request.url(),
+                content_type.as_deref(),
+                &body,
+                recipes,
+                redaction.as_ref(),
+                root.as_deref(),
+            ),
+            Err(e) => error(400, &e.to_string()),
+        };
+
+        let response = Response::from_string(value.to_string())
+            .with_status_code(code)
+            .with_header(json_type.clone());
+        // The client may have gone away; that's no reason to stop serving.
+        let _ = request.respond(response);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, FilmMode, FujifilmSettings};
+
+    #[test]
+    fn answers_with_settings_and_status_codes() {
+        let mut settings = FujifilmSettings::new();
+        settings.film_mode = FilmMode::Eterna;
+        let photo = fixtures::jpeg(&settings);
+        let recipes = [Recipe {
+            name: "Cinema".to_string(),
+            settings: settings.clone(),
+        }];
+
+        let root = std::env::temp_dir()
+            .canonicalize()
+            .unwrap()
+            .join(format!("film-detect-{}-serve", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("photos")).unwrap();
+        let path = root.join("photos/DSCF0001.JPG");
+        std::fs::write(&path, &photo).unwrap();
+        let get = |path: &str| {
+            let url = format!("/settings?path={}", path).replace(' ', "%20");
+            respond("GET", &url, None, &[], &recipes, None, Some(&root))
+        };
+
+        let (code, value) = get(&path.to_string_lossy());
+        assert_eq!(code, 200);
+        assert_eq!(value["film_mode"], "Eterna");
+        assert_eq!(value["recipe"], "Cinema");
+        assert_eq!(get("photos/DSCF0001.JPG").0, 200);
+
+        // Nothing outside the root, not even through a link, and nothing
+        // that isn't a regular file.
+        let outside = root.with_extension("JPG");
+        std::fs::write(&outside, &photo).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("photos/link.JPG")).unwrap();
+        assert_eq!(get(&outside.to_string_lossy()), get("/nonexistent.JPG"));
+        assert_eq!(get("../").0, 404);
+        assert_eq!(get("photos/link.JPG").0, 404);
+        assert_eq!(get("photos").0, 403);
+        let url = format!("/settings?path={}", path.display()).replace(' ', "%20");
+        let (code, value) = respond("GET", &url, None, &[], &recipes, None, None);
+        assert_eq!(code, 403);
+        assert!(value["error"].as_str().unwrap().contains("--root"));
+        std::fs::remove_file(outside).unwrap();
+
+        let (code, value) = respond("POST", "/settings", None, &photo, &[], None, None);
+        assert_eq!(code, 200);
+        assert_eq!(value["film_mode"], "Eterna");
+        assert!(value.get("recipe").is_none());
+
+        let mut form = b"--xyz\r\nContent-Disposition: form-data; name=\"album\"\r\n\r\nSummer\r\n\
+              --xyz\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"a.JPG\"\r\n\
+              Content-Type: image/jpeg\r\n\r\n"
+            .to_vec();
+        form.extend_from_slice(&photo);
+        form.extend_from_slice(b"\r\n--xyz--\r\n");
+        let content_type = Some("multipart/form-data; boundary=xyz");
+        let (code, value) = respond(
+            "POST",
+            "/settings",
+            content_type,
+            &form,
+            &recipes,
+            None,
+            None,
+        );
+        assert_eq!(code, 200);
+        assert_eq!(value["recipe"], "Cinema");
+
+        let (code, _) = respond("POST", "/settings", None, b"not a photo", &[], None, None);
+        assert_eq!(code, 422);
+        assert_eq!(get("/nonexistent.JPG").0, 404);
+        let (code, _) = respond("GET", "/settings", None, &[], &[], None, None);
+        assert_eq!(code, 400);
+        let (code, _) = respond("DELETE", "/settings", None, &[], &[], None, None);
+        assert_eq!(code, 405);
+        let (code, _) = respond("GET", "/", None, &[], &[], None, None);
+        assert_eq!(code, 404);
+    }
+}
@@ -0,0 +1,284 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Every setting knows how to go to and from its MakerNote value, how to show
+// itself to people, and how to identify itself in JSON.  Most settings are a
+// fixed list of values and describe themselves with a single `Choice` table;
+// the numeric ones implement `Setting` by hand.
+
+use crate::FilmError;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt;
+#[cfg(feature = "serde")]
+use serde::de::{self, Visitor};
+#[cfg(feature = "serde")]
+use serde::{Deserializer, Serializer};
+
+/// A value of a camera setting.
+pub trait Setting: Sized {
+    /// What the value looks like in the MakerNote.
+    type Raw;
+
+    /// The setting's name in error messages, e.g. "sharpness".
+    const NAME: &'static str;
+
+    fn decode(raw: Self::Raw) -> Result<Self, FilmError>;
+
+    /// `None` when the camera doesn't write this value to the tag, e.g. the
+    /// film mode of a monochrome simulation.
+    fn encode(&self) -> Option<Self::Raw>;
+
+    /// A stable identifier, e.g. `MediumHard`.  Used in JSON.
+    fn id(&self) -> String;
+
+    /// What the camera menus show, e.g. `+1`.
+    fn label(&self) -> String;
+
+    /// The position on the camera's scale, for settings that have one.
+    fn step(&self) -> Option<f64>;
+
+    /// Accepts an identifier, a label or a step, ignoring case.
+    fn parse(s: &str) -> Option<Self>;
+}
+
+/// One row of a setting's table.
+pub struct Choice<T: 'static> {
+    pub value: T,
+    pub raw: Option<i32>,
+    pub id: &'static str,
+    pub label: &'static str,
+    pub step: Option<i8>,
+}
+
+/// A setting with a fixed list of values.
+pub trait Choices: Copy + PartialEq + 'static {
+    const NAME: &'static str;
+    const CHOICES: &'static [Choice<Self>];
+
+    fn choice(&self) -> &'static Choice<Self> {
+        Self::CHOICES
+            .iter()
+            .find(|c| c.value == *self)
+            .expect("every value has a choice")
+    }
+}
+
+pub(crate) fn unexpected(raw: impl fmt::Display, name: &str) -> FilmError {
+    FilmError::UnexpectedValue(format!("Failed to parse {} as {} value.", raw, name))
+}
+
+impl<T: Choices> Setting for T {
+    type Raw = i32;
+    const NAME: &'static str = <T as Choices>::NAME;
+
+    fn decode(raw: i32) -> Result<Self, FilmError> {
+        T::CHOICES
+            .iter()
+            .find(|c| c.raw == Some(raw))
+            .map(|c| c.value)
+            .ok_or_else(|| unexpected(raw, <T as Choices>::NAME))
+    }
+
+    fn encode(&self) -> Option<i32> {
+        self.choice().raw
+    }
+
+    fn id(&self) -> String {
+        self.choice().id.to_string()
+    }
+
+    fn label(&self) -> String {
+        self.choice().label.to_string()
+    }
+
+    fn step(&self) -> Option<f64> {
+        self.choice().step.map(f64::from)
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let step = s.parse::<f64>().ok();
+        T::CHOICES
+            .iter()
+            .find(|c| {
+                c.id.eq_ignore_ascii_case(s)
+                    || c.label.eq_ignore_ascii_case(s)
+                    || (step.is_some() && c.step.map(f64::from) == step)
+            })
+            .map(|c| c.value)
+    }
+}
+
+/// Settings with a step are written as that number, integers for whole
+/// steps; everything else as its identifier.
+#[cfg(feature = "serde")]
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Setting,
+    S: Serializer,
+{
+    match value.step() {
+        Some(step) if step % 1.0 == 0.0 => serializer.serialize_i64(step as i64),
+        Some(step) => serializer.serialize_f64(step),
+        None => serializer.serialize_str(&value.id()),
+    }
+}
+
+/// Accepts anything `Setting::parse` does, as a string or a number.
+#[cfg(feature = "serde")]
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Setting,
+    D: Deserializer<'de>,
+{
+    struct SettingVisitor<T>(core::marker::PhantomData<T>);
+
+    impl<T: Setting> SettingVisitor<T> {
+        fn parse<E: de::Error>(&self, s: &str) -> Result<T, E> {
+            T::parse(s).ok_or_else(|| E::custom(format!("{} is not a valid {} value", s, T::NAME)))
+        }
+    }
+
+    impl<T: Setting> Visitor<'_> for SettingVisitor<T> {
+        type Value = T;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a {} value", T::NAME)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+            self.parse(v)
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<T, E> {
+            self.parse(&v.to_string())
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<T, E> {
+            self.parse(&v.to_string())
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<T, E> {
+            self.parse(&v.to_string())
+        }
+    }
+
+    deserializer.deserialize_any(SettingVisitor(core::marker::PhantomData))
+}
+
+/// Display, Serialize and Deserialize in terms of `Setting`.
+macro_rules! setting_traits {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl core::fmt::Display for $t {
+                fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    write!(f, "{}", $crate::Setting::label(self))
+                }
+            }
+
+            #[cfg(feature = "serde")]
+            impl serde::Serialize for $t {
+                fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    $crate::setting::serialize(self, serializer)
+                }
+            }
+
+            #[cfg(feature = "serde")]
+            impl<'de> serde::Deserialize<'de> for $t {
+                fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    $crate::setting::deserialize(deserializer)
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn round_trips<T: Choices + Setting<Raw = i32> + core::fmt::Debug>() {
+        for choice in T::CHOICES {
+            let value = choice.value;
+            if let Some(raw) = value.encode() {
+                assert_eq!(T::decode(raw).unwrap(), value);
+            }
+            assert_eq!(T::parse(&value.id()), Some(value));
+            assert_eq!(T::parse(&value.label()), Some(value));
+            if let Some(step) = value.step() {
+                assert_eq!(T::parse(&step.to_string()), Some(value));
+            }
+        }
+    }
+
+    #[test]
+    fn tables_round_trip() {
+        round_trips::<Saturation>();
+        round_trips::<MonochromeFilter>();
+        round_trips::<Sharpness>();
+        round_trips::<DynamicRange>();
+        round_trips::<WhiteBalance>();
+        round_trips::<NoiseReduction>();
+        round_trips::<GrainRoughness>();
+        round_trips::<GrainSize>();
+        round_trips::<ColorChrome>();
+        round_trips::<ColorChromeFxBlue>();
+        round_trips::<FilmMode>();
+        round_trips::<AdvancedFilter>();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        assert_eq!(serde_json::json!(Sharpness::MediumHard).to_string(), "1");
+        assert_eq!(
+            serde_json::json!(FilmMode::ClassicChrome).to_string(),
+            "\"ClassicChrome\""
+        );
+
+        // Identifiers, labels and steps are all accepted.
+        for json in ["\"MediumHard\"", "\"+1\"", "1"] {
+            assert_eq!(
+                serde_json::from_str::<Sharpness>(json).unwrap(),
+                Sharpness::MediumHard
+            );
+        }
+        assert_eq!(
+            serde_json::from_str::<FilmMode>("\"classic chrome\"").unwrap(),
+            FilmMode::ClassicChrome
+        );
+        assert!(serde_json::from_str::<Sharpness>("5").is_err());
+    }
+
+    #[test]
+    fn fine_tune() {
+        let fine_tune = WhiteBalanceFineTune::new(2, -5).unwrap();
+        assert_eq!(fine_tune.encode(), Some((40, -100)));
+        assert_eq!(WhiteBalanceFineTune::decode((40, -100)).unwrap(), fine_tune);
+        assert_eq!(WhiteBalanceFineTune::parse("R:2 B:-5"), Some(fine_tune));
+        // Off-step and out of range values round and clamp.
+        assert_eq!(
+            WhiteBalanceFineTune::decode((45, -1000)).unwrap(),
+            WhiteBalanceFineTune::new(2, -9).unwrap()
+        );
+        assert_eq!(
+            WhiteBalanceFineTune::decode((i32::MAX, 19)).unwrap(),
+            WhiteBalanceFineTune::new(9, 1).unwrap()
+        );
+        assert!(WhiteBalanceFineTune::new(10, 0).is_none());
+    }
+}
@@ -0,0 +1,187 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "exif")]
+use exif::{In, Tag, Value};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How the photo was exposed, from the standard EXIF fields.  Anything the
+/// camera didn't record is `None`.
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ShotInfo {
+    // In seconds.
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    // In EV.
+    pub exposure_compensation: Option<f64>,
+    pub lens_model: Option<String>,
+    // In millimeters.
+    pub focal_length: Option<f64>,
+    pub focal_length_35mm: Option<u32>,
+}
+
+#[cfg(feature = "exif")]
+fn field(exif: &exif::Exif, tag: Tag) -> Option<&Value> {
+    exif.get_field(tag, In::PRIMARY).map(|f| &f.value)
+}
+
+#[cfg(feature = "exif")]
+fn float(exif: &exif::Exif, tag: Tag) -> Option<f64> {
+    match field(exif, tag)? {
+        Value::Rational(v) => v.first().map(|r| r.to_f64()),
+        Value::SRational(v) => v.first().map(|r| r.to_f64()),
+        _ => None,
+    }
+    .filter(|n| n.is_finite())
+}
+
+#[cfg(feature = "exif")]
+fn text(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    match field(exif, tag)? {
+        Value::Ascii(v) => v
+            .first()
+            .map(|s| String::from_utf8_lossy(s).trim().to_string())
+            .filter(|s| !s.is_empty()),
+        _ => None,
+    }
+}
+
+// f64::round needs std.  Rounds half away from zero like it does; the values
+// here are far too small for the cast to truncate.
+fn round_integer(n: f64) -> f64 {
+    let whole = n as i64 as f64;
+    match n - whole {
+        r if r >= 0.5 => whole + 1.0,
+        r if r <= -0.5 => whole - 1.0,
+        _ => whole,
+    }
+}
+
+// Trims the noise of rational arithmetic, e.g. 0.66666 -> 0.7.
+fn round(n: f64) -> f64 {
+    round_integer(n * 10.0) / 10.0
+}
+
+impl ShotInfo {
+    /// `None` when the file has none of the fields.
+    #[cfg(feature = "exif")]
+    pub(crate) fn from_exif(exif: &exif::Exif) -> Option<Self> {
+        let info = ShotInfo {
+            exposure_time: float(exif, Tag::ExposureTime),
+            f_number: float(exif, Tag::FNumber),
+            iso: field(exif, Tag::PhotographicSensitivity).and_then(|v| v.get_uint(0)),
+            exposure_compensation: float(exif, Tag::ExposureBiasValue),
+            lens_model: text(exif, Tag::LensModel),
+            focal_length: float(exif, Tag::FocalLength),
+            focal_length_35mm: field(exif, Tag::FocalLengthIn35mmFilm)
+                .and_then(|v| v.get_uint(0))
+                .filter(|n| *n > 0),
+        };
+        (info != ShotInfo::default()).then_some(info)
+    }
+
+    /// The recorded values as label/value pairs.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![];
+
+        if let Some(time) = self.exposure_time {
+            let shutter = if time > 0.0 && time < 1.0 {
+                format!("1/{}", round_integer(1.0 / time))
+            } else {
+                format!("{}", round(time))
+            };
+            fields.push(("Shutter Speed", format!("{} s", shutter)));
+        }
+        if let Some(f_number) = self.f_number {
+            fields.push(("Aperture", format!("f/{}", round(f_number))));
+        }
+        if let Some(iso) = self.iso {
+            fields.push(("ISO", iso.to_string()));
+        }
+        if let Some(ev) = self.exposure_compensation {
+            let sign = if round(ev) > 0.0 { "+" } else { "" };
+            fields.push(("Exposure Compensation", format!("{}{} EV", sign, round(ev))));
+        }
+        if let Some(lens) = &self.lens_model {
+            fields.push(("Lens", lens.clone()));
+        }
+        match (self.focal_length, self.focal_length_35mm) {
+            (Some(mm), Some(equivalent)) => fields.push((
+                "Focal Length",
+                format!("{} mm ({} mm in 35mm format)", round(mm), equivalent),
+            )),
+            (Some(mm), None) => fields.push(("Focal Length", format!("{} mm", round(mm)))),
+            _ => {}
+        }
+
+        fields
+    }
+}
+
+impl fmt::Display for ShotInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(label, value)| format!("{}: {}", label, value))
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(all(test, feature = "exif"))]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn reads_shot_info() {
+        let data = fixtures::jpeg_with_exif(&[
+            fixtures::rational(0x829a, 1, 250),
+            fixtures::rational(0x829d, 28, 10),
+            fixtures::short(0x8827, 400),
+            fixtures::srational(0x9204, -2, 3),
+            fixtures::rational(0x920a, 23, 1),
+            fixtures::short(0xa405, 35),
+            fixtures::ascii(0xa434, "XF23mmF2 R WR"),
+        ]);
+        let exif = crate::container::read_exif(&mut std::io::Cursor::new(data)).unwrap();
+        let info = ShotInfo::from_exif(&exif).unwrap();
+
+        assert_eq!(info.iso, Some(400));
+        assert_eq!(
+            info.to_string(),
+            "Shutter Speed: 1/250 s\n\
+             Aperture: f/2.8\n\
+             ISO: 400\n\
+             Exposure Compensation: -0.7 EV\n\
+             Lens: XF23mmF2 R WR\n\
+             Focal Length: 23 mm (35 mm in 35mm format)"
+        );
+
+        let empty =
+            crate::container::read_exif(&mut std::io::Cursor::new(fixtures::jpeg_with_exif(&[])));
+        assert_eq!(ShotInfo::from_exif(&empty.unwrap()), None);
+    }
+}
@@ -0,0 +1,49 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The JavaScript API of the WebAssembly build:
+//
+//     import init, { detect } from "./pkg/film_detect.js";
+//
+//     await init();
+//     const bytes = new Uint8Array(await file.arrayBuffer());
+//     const settings = JSON.parse(detect(bytes, recipesJson));
+//
+// Nothing leaves the browser.
+
+use crate::{find_recipe, get_fujifilm_settings_from_bytes, Recipe};
+use wasm_bindgen::prelude::*;
+
+/// Returns the photo's settings as the JSON `--json` prints.  `recipes` is an
+/// optional JSON array of recipes in the format of the recipe files; the
+/// first one that matches is added as "recipe".  Throws an `Error` with the
+/// message the command line would print when the photo can't be read.
+#[wasm_bindgen]
+pub fn detect(data: &[u8], recipes: Option<String>) -> Result<String, JsError> {
+    let recipes: Vec<Recipe> = match recipes {
+        Some(json) => serde_json::from_str(&json)
+            .map_err(|e| JsError::new(&format!("Invalid recipe: {}", e)))?,
+        None => vec![],
+    };
+
+    let settings =
+        get_fujifilm_settings_from_bytes(data).map_err(|e| JsError::new(&e.to_string()))?;
+    let mut output = serde_json::json!(settings);
+    if let Some(recipe) = find_recipe(&settings, &recipes) {
+        output["recipe"] = serde_json::json!(recipe.name);
+    }
+    Ok(output.to_string())
+}
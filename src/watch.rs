@@ -0,0 +1,364 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Watches a hot folder for new photos.  A photo is handed out only once it is
+// completely written: on Linux that is when inotify says the writer closed it
+// or when it was moved in, which is how tethering and import tools that write
+// to a temporary name finish.  Elsewhere the folder is polled, and a photo is
+// ready once its size and modification time stop changing.  Linux falls back
+// on that too for photos that were already in a new directory before it
+// could be watched, since their close may have been missed.
+
+use crate::files::collect_files;
+use crate::FilmError;
+use std::path::{Path, PathBuf};
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+#[cfg(target_os = "linux")]
+use crate::files::{is_photo, is_real_dir};
+#[cfg(target_os = "linux")]
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+#[cfg(not(target_os = "linux"))]
+use std::collections::HashSet;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Size and modification time, or `None` when the file is gone.
+fn file_state(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// What `Watcher::wait` found.
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// A photo that is completely written.
+    Photo(PathBuf),
+    /// A directory that couldn't be followed, usually because it was removed
+    /// again right away.  The rest of the tree is still watched.
+    Skipped(PathBuf, FilmError),
+}
+
+/// Waits for photos to land in a directory or any of its subdirectories.
+/// Photos that are already there when the watch starts are left alone.
+#[cfg(target_os = "linux")]
+pub struct Watcher {
+    inotify: Inotify,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    buffer: Vec<u8>,
+    // Photos found in a new directory before it was watched, with their
+    // state at the last poll.  Each is reported once, when it is closed or
+    // when it holds still for a poll, whichever comes first.
+    pending: HashMap<PathBuf, (u64, SystemTime)>,
+    // Subdirectories skipped while starting, for the first `wait`.
+    skipped: Vec<WatchEvent>,
+}
+
+/// Waits for photos to land in a directory or any of its subdirectories.
+/// Photos that are already there when the watch starts are left alone.
+#[cfg(not(target_os = "linux"))]
+pub struct Watcher {
+    dir: PathBuf,
+    // Size and modification time at the last poll of photos not yet ready.
+    pending: HashMap<PathBuf, (u64, SystemTime)>,
+    seen: HashSet<PathBuf>,
+    // Subdirectories that couldn't be read at the last poll, reported once.
+    unreadable: HashSet<PathBuf>,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    pub fn new(dir: &Path) -> Result<Self, FilmError> {
+        let mut watcher = Watcher {
+            inotify: Inotify::init()?,
+            dirs: HashMap::new(),
+            buffer: vec![0; 4096],
+            pending: HashMap::new(),
+            skipped: vec![],
+        };
+        let mut skipped = vec![];
+        watcher.watch_tree(dir, &mut skipped)?;
+        watcher.skipped = skipped;
+        Ok(watcher)
+    }
+
+    // Watches `dir` and every directory under it.  Only an error for `dir`
+    // itself is returned; subdirectories that fail go into `skipped`.
+    fn watch_tree(&mut self, dir: &Path, skipped: &mut Vec<WatchEvent>) -> Result<(), FilmError> {
+        let mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE;
+        let wd = self.inotify.watches().add(dir, mask)?;
+        self.dirs.insert(wd, dir.to_path_buf());
+
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if is_real_dir(&path) {
+                if let Err(e) = self.watch_tree(&path, skipped) {
+                    skipped.push(WatchEvent::Skipped(path, e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Blocks until at least one new photo is completely written, or a
+    /// directory had to be skipped, and returns everything found.  Only
+    /// errors of the watch as a whole are returned as `Err`.
+    pub fn wait(&mut self) -> Result<Vec<WatchEvent>, FilmError> {
+        let mut found = std::mem::take(&mut self.skipped);
+        loop {
+            // With photos pending, look again after a poll instead of waiting
+            // for an event that may never come.
+            let polled: Vec<PathBuf> = self.pending.keys().cloned().collect();
+            let mut buffer = std::mem::take(&mut self.buffer);
+            let events = if polled.is_empty() {
+                self.inotify.read_events_blocking(&mut buffer).map(Some)
+            } else {
+                std::thread::sleep(POLL_INTERVAL);
+                match self.inotify.read_events(&mut buffer) {
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+                    result => result.map(Some),
+                }
+            };
+
+            let mut new_dirs = vec![];
+            for event in events?.into_iter().flatten() {
+                if event.mask.contains(EventMask::IGNORED) {
+                    // The directory itself went away.
+                    self.dirs.remove(&event.wd);
+                    continue;
+                }
+                let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) else {
+                    continue;
+                };
+                let path = dir.join(name);
+
+                if event.mask.contains(EventMask::ISDIR) {
+                    // A directory moved in is complete, but one that was just
+                    // created may still be filled.
+                    new_dirs.push((path, event.mask.contains(EventMask::MOVED_TO)));
+                } else if event
+                    .mask
+                    .intersects(EventMask::CLOSE_WRITE | EventMask::MOVED_TO)
+                    && is_photo(&path)
+                {
+                    self.pending.remove(&path);
+                    found.push(WatchEvent::Photo(path));
+                }
+            }
+            self.buffer = buffer;
+
+            for path in polled {
+                let Some(state) = self.pending.get(&path) else {
+                    continue;
+                };
+                match file_state(&path) {
+                    Some(now) if now != *state => {
+                        self.pending.insert(path, now);
+                    }
+                    Some(_) => {
+                        self.pending.remove(&path);
+                        found.push(WatchEvent::Photo(path));
+                    }
+                    None => {
+                        self.pending.remove(&path);
+                    }
+                }
+            }
+
+            for (dir, moved) in new_dirs {
+                if let Err(e) = self.watch_new_dir(&dir, moved, &mut found) {
+                    found.push(WatchEvent::Skipped(dir, e));
+                }
+            }
+            if !found.is_empty() {
+                return Ok(found);
+            }
+        }
+    }
+
+    // Photos can land in a new directory before it is watched.  A directory
+    // moved in is complete, so they are reported right away.  In one that was
+    // just created they may still be written, so they wait in `pending`.
+    fn watch_new_dir(
+        &mut self,
+        dir: &Path,
+        moved: bool,
+        found: &mut Vec<WatchEvent>,
+    ) -> Result<(), FilmError> {
+        self.watch_tree(dir, found)?;
+
+        let mut photos = vec![];
+        let mut failed = vec![];
+        collect_files(dir, &mut photos, &mut failed)?;
+        found.extend(
+            failed
+                .into_iter()
+                .map(|(dir, e)| WatchEvent::Skipped(dir, e)),
+        );
+        for photo in photos {
+            if moved {
+                found.push(WatchEvent::Photo(photo));
+            } else if let Some(state) = file_state(&photo) {
+                self.pending.insert(photo, state);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    pub fn new(dir: &Path) -> Result<Self, FilmError> {
+        let mut existing = vec![];
+        collect_files(dir, &mut existing, &mut vec![])?;
+        Ok(Watcher {
+            dir: dir.to_path_buf(),
+            pending: HashMap::new(),
+            seen: existing.into_iter().collect(),
+            unreadable: HashSet::new(),
+        })
+    }
+
+    /// Blocks until at least one new photo is completely written, or a
+    /// directory had to be skipped, and returns everything found.  Only
+    /// errors of the watch as a whole are returned as `Err`.
+    pub fn wait(&mut self) -> Result<Vec<WatchEvent>, FilmError> {
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+
+            // Usually a subdirectory removed during the walk; the next poll
+            // will see the tree as it is then.
+            let mut files = vec![];
+            let mut failed = vec![];
+            if let Err(e) = collect_files(&self.dir, &mut files, &mut failed) {
+                return Ok(vec![WatchEvent::Skipped(self.dir.clone(), e)]);
+            }
+
+            let mut found = vec![];
+            let mut unreadable = HashSet::new();
+            for (dir, e) in failed {
+                if !self.unreadable.contains(&dir) {
+                    found.push(WatchEvent::Skipped(dir.clone(), e));
+                }
+                unreadable.insert(dir);
+            }
+            self.unreadable = unreadable;
+            for file in files {
+                if self.seen.contains(&file) {
+                    continue;
+                }
+                // It may be gone again already.
+                let Some(state) = file_state(&file) else {
+                    continue;
+                };
+                if self.pending.get(&file) == Some(&state) {
+                    self.pending.remove(&file);
+                    self.seen.insert(file.clone());
+                    found.push(WatchEvent::Photo(file));
+                } else {
+                    self.pending.insert(file, state);
+                }
+            }
+
+            if !found.is_empty() {
+                return Ok(found);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn photos(events: Vec<WatchEvent>) -> Vec<PathBuf> {
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                WatchEvent::Photo(path) => Some(path),
+                WatchEvent::Skipped(..) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn waits_for_written_photos() {
+        let dir = std::env::temp_dir().join(format!("film-detect-{}-watch", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let hot = dir.join("hot");
+        let card = dir.join("card");
+        std::fs::create_dir_all(&hot).unwrap();
+        std::fs::create_dir_all(card.join("100_FUJI")).unwrap();
+        // The watcher never reads the files.
+        let photo = b"\xff\xd8\xff\xd9";
+        std::fs::write(hot.join("0.JPG"), photo).unwrap();
+
+        let mut watcher = Watcher::new(&hot).unwrap();
+        std::fs::write(hot.join("notes.txt"), "not a photo").unwrap();
+        std::fs::write(hot.join("1.JPG"), photo).unwrap();
+        assert_eq!(photos(watcher.wait().unwrap()), [hot.join("1.JPG")]);
+
+        // A whole folder moved in from the card.
+        std::fs::write(card.join("100_FUJI/2.JPG"), photo).unwrap();
+        std::fs::rename(card.join("100_FUJI"), hot.join("100_FUJI")).unwrap();
+        assert_eq!(
+            photos(watcher.wait().unwrap()),
+            [hot.join("100_FUJI/2.JPG")]
+        );
+
+        // Written to a new folder before the watch on it was added.
+        std::fs::create_dir(hot.join("101_FUJI")).unwrap();
+        std::fs::write(hot.join("101_FUJI/3.JPG"), photo).unwrap();
+        assert_eq!(
+            photos(watcher.wait().unwrap()),
+            [hot.join("101_FUJI/3.JPG")]
+        );
+
+        // A folder gone before it could be watched doesn't end the watch.
+        std::fs::create_dir(hot.join("gone")).unwrap();
+        std::fs::remove_dir(hot.join("gone")).unwrap();
+        std::fs::write(hot.join("4.JPG"), photo).unwrap();
+        let mut found = vec![];
+        while !found.contains(&hot.join("4.JPG")) {
+            found.extend(photos(watcher.wait().unwrap()));
+        }
+        assert_eq!(found, [hot.join("4.JPG")]);
+
+        // Still being written when the watch on its new folder is added.
+        std::fs::create_dir(hot.join("102_FUJI")).unwrap();
+        let mut file = std::fs::File::create(hot.join("102_FUJI/5.JPG")).unwrap();
+        file.write_all(&photo[..2]).unwrap();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            file.write_all(&photo[2..]).unwrap();
+        });
+        assert_eq!(
+            photos(watcher.wait().unwrap()),
+            [hot.join("102_FUJI/5.JPG")]
+        );
+        assert_eq!(
+            std::fs::metadata(hot.join("102_FUJI/5.JPG")).unwrap().len(),
+            4
+        );
+        writer.join().unwrap();
+        // Reported once, and not kept around.
+        assert!(watcher.pending.is_empty());
+    }
+}
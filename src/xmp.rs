@@ -0,0 +1,604 @@
+// film detect
+// Copyright (C) 2024 Honza Pokorny <honza@pokorny.ca>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Writes the decoded settings into XMP so that DAMs that don't read MakerNotes
+// (Lightroom, digiKam, darktable) can filter by film simulation.  We own a
+// single rdf:Description in our namespace and replace it on every run.  Tools
+// such as ExifTool and Lightroom merge every namespace into one shared
+// description; there only our own properties are dropped.  Everything else in
+// the packet is copied through untouched.  Keywords are merged into
+// dc:subject.
+
+use crate::jpeg::write_atomically;
+use crate::{FilmError, FilmMode, FujifilmSettings};
+use quick_xml::escape::escape;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::path::{Path, PathBuf};
+
+pub const NAMESPACE: &str = "https://github.com/honza/film-detect/ns/1.0/";
+const PREFIX: &str = "filmdetect";
+const KEYWORDS: &str = "Keywords";
+
+const EMPTY: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+/// `photo.jpg` -> `photo.xmp`
+pub fn sidecar_path(image: &Path) -> PathBuf {
+    image.with_extension("xmp")
+}
+
+/// Creates or updates the XMP sidecar next to `image` and returns its path.
+pub fn write_xmp_sidecar(
+    image: &Path,
+    settings: &FujifilmSettings,
+    recipe: Option<&str>,
+) -> Result<PathBuf, FilmError> {
+    let path = sidecar_path(image);
+
+    let existing = match std::fs::read_to_string(&path) {
+        Ok(xmp) => xmp,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => EMPTY.to_string(),
+        Err(e) => return Err(e.into()),
+    };
+
+    write_atomically(&path, update_xmp(&existing, settings, recipe)?.as_bytes())?;
+    Ok(path)
+}
+
+/// The dc:subject keywords we add for a photo.  A photo without a recorded
+/// film mode gets none for it.
+pub fn keywords(settings: &FujifilmSettings, recipe: Option<&str>) -> Vec<String> {
+    let mut keywords = vec![];
+    if *settings.film_mode() != FilmMode::None {
+        keywords.push(settings.film_mode().to_string());
+    }
+    if let Some(recipe) = recipe {
+        keywords.push(recipe.to_string());
+    }
+    keywords
+}
+
+// "Color Chrome FX Blue" -> "ColorChromeFXBlue"
+fn property_name(label: &str) -> String {
+    label
+        .split_whitespace()
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn description(settings: &FujifilmSettings, recipe: Option<&str>) -> String {
+    let mut out = format!(
+        "\n  <rdf:Description rdf:about=\"\"\n    xmlns:{}=\"{}\">",
+        PREFIX, NAMESPACE
+    );
+    let mut fields = settings.fields();
+    if let Some(recipe) = recipe {
+        fields.push(("Recipe", recipe.to_string()));
+    }
+    for (label, value) in fields {
+        let name = property_name(label);
+        out.push_str(&format!(
+            "\n   <{}:{}>{}</{}:{}>",
+            PREFIX,
+            name,
+            escape(&value),
+            PREFIX,
+            name
+        ));
+    }
+    out.push_str("\n  </rdf:Description>");
+    out
+}
+
+// An rdf:Bag of `keywords`, with `indent` before its own closing tag.
+fn bag(indent: &str, keywords: &[String]) -> String {
+    let mut out = String::from("<rdf:Bag>");
+    for keyword in keywords {
+        out.push_str(&format!("{} <rdf:li>{}</rdf:li>", indent, escape(keyword)));
+    }
+    out.push_str(&format!("{}</rdf:Bag>", indent));
+    out
+}
+
+// The record of the keywords we put in dc:subject, so the next run can take
+// them out again when they no longer apply.
+fn keywords_record(indent: &str, keywords: &[String]) -> String {
+    if keywords.is_empty() {
+        return String::new();
+    }
+    format!(
+        "{}<{}:{} xmlns:{}=\"{}\">{} {}{}</{}:{}>",
+        indent,
+        PREFIX,
+        KEYWORDS,
+        PREFIX,
+        NAMESPACE,
+        indent,
+        bag(&format!("{} ", indent), keywords),
+        indent,
+        PREFIX,
+        KEYWORDS
+    )
+}
+
+fn subject_description(keywords: &[String], recorded: &[String]) -> String {
+    format!(
+        "\n  <rdf:Description rdf:about=\"\"\n    xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n   <dc:subject>\n    {}\n   </dc:subject>{}\n  </rdf:Description>",
+        bag("\n    ", keywords),
+        keywords_record("\n   ", recorded)
+    )
+}
+
+fn is_keywords_record(name: &[u8]) -> bool {
+    is_our_name(name) && name[PREFIX.len() + 1..] == *KEYWORDS.as_bytes()
+}
+
+fn is_our_name(name: &[u8]) -> bool {
+    name.strip_prefix(PREFIX.as_bytes())
+        .is_some_and(|rest| rest.starts_with(b":"))
+}
+
+// Our namespace declaration or one of our properties.
+fn is_our_attribute(a: &Attribute) -> bool {
+    let key = a.key.as_ref();
+    is_our_name(key) || key.strip_prefix(b"xmlns:") == Some(PREFIX.as_bytes())
+}
+
+// A property of ours, or a description that holds nothing but ours.
+fn is_ours(e: &BytesStart) -> bool {
+    if is_our_name(e.name().as_ref()) {
+        return true;
+    }
+    let mut attributes = e.attributes().flatten();
+    e.name().as_ref() == b"rdf:Description"
+        && attributes.clone().any(|a| is_our_attribute(&a))
+        && attributes.all(|a| a.key.as_ref() == b"rdf:about" || is_our_attribute(&a))
+}
+
+// A shared description without our namespace and properties, or `None` when
+// it has none of them.
+fn without_ours(e: &BytesStart) -> Option<BytesStart<'static>> {
+    if e.name().as_ref() != b"rdf:Description"
+        || !e.attributes().flatten().any(|a| is_our_attribute(&a))
+    {
+        return None;
+    }
+    let mut stripped = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+    stripped.extend_attributes(e.attributes().flatten().filter(|a| !is_our_attribute(a)));
+    Some(stripped)
+}
+
+fn has_subject(xmp: &str) -> Result<bool, FilmError> {
+    let mut reader = Reader::from_str(xmp);
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"dc:subject" => {
+                return Ok(true)
+            }
+            Event::Eof => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+// The text of every rdf:li inside the `property` elements.
+fn items(xmp: &str, property: impl Fn(&[u8]) -> bool) -> Result<Vec<String>, FilmError> {
+    let mut reader = Reader::from_str(xmp);
+    let mut inside = false;
+    let mut items = vec![];
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if property(e.name().as_ref()) => inside = true,
+            Event::End(e) if property(e.name().as_ref()) => inside = false,
+            Event::Text(t) if inside => {
+                let text = t.unescape()?;
+                if !text.trim().is_empty() {
+                    items.push(text.trim().to_string());
+                }
+            }
+            Event::Eof => return Ok(items),
+            _ => {}
+        }
+    }
+}
+
+/// The keywords already in dc:subject.
+pub fn subject_keywords(xmp: &str) -> Result<Vec<String>, FilmError> {
+    items(xmp, |name| name == b"dc:subject")
+}
+
+/// The keywords an earlier run put in dc:subject that `keywords` no longer
+/// has.  `update_xmp` and `merge_keywords` take them out again.
+pub fn stale_keywords(xmp: &str, keywords: &[String]) -> Result<Vec<String>, FilmError> {
+    let subject = subject_keywords(xmp)?;
+    Ok(items(xmp, is_keywords_record)?
+        .into_iter()
+        .filter(|k| !keywords.contains(k) && subject.contains(k))
+        .collect())
+}
+
+/// Replaces our description in an existing XMP packet and merges the keywords
+/// into dc:subject, taking out the ones an earlier run added that no longer
+/// apply.
+pub fn update_xmp(
+    xmp: &str,
+    settings: &FujifilmSettings,
+    recipe: Option<&str>,
+) -> Result<String, FilmError> {
+    rewrite(
+        xmp,
+        Some(description(settings, recipe)),
+        &keywords(settings, recipe),
+    )
+}
+
+/// Merges the keywords into dc:subject, takes out the ones an earlier run
+/// added that no longer apply, and leaves everything else alone.
+pub fn merge_keywords(xmp: &str, keywords: &[String]) -> Result<String, FilmError> {
+    rewrite(xmp, None, keywords)
+}
+
+/// An empty packet with the xpacket wrapper that embedded XMP needs.
+pub fn new_packet() -> String {
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n{}<?xpacket end=\"w\"?>",
+        EMPTY
+    )
+}
+
+// When `description` is given it replaces any description of ours already in
+// the packet.  Keywords recorded as ours that aren't in `keywords` any more
+// are taken out of dc:subject.
+fn rewrite(
+    xmp: &str,
+    description: Option<String>,
+    keywords: &[String],
+) -> Result<String, FilmError> {
+    let has_subject = has_subject(xmp)?;
+    let replace_ours = description.is_some();
+
+    let ours = items(xmp, is_keywords_record)?;
+    let stale: Vec<&String> = ours.iter().filter(|k| !keywords.contains(k)).collect();
+    let subject = subject_keywords(xmp)?;
+    let added: Vec<String> = keywords
+        .iter()
+        .filter(|k| !subject.contains(k))
+        .cloned()
+        .collect();
+    // A keyword someone else put there stays theirs.
+    let recorded: Vec<String> = keywords
+        .iter()
+        .filter(|k| ours.contains(k) || !subject.contains(k))
+        .cloned()
+        .collect();
+
+    let mut reader = Reader::from_str(xmp);
+    let mut writer = Writer::new(Vec::new());
+
+    // Whitespace is held back until we know whether the next element is one
+    // we drop, so that repeated runs don't pile up blank lines.
+    let mut pending = String::new();
+    let mut skip_depth = 0;
+    let mut in_subject = false;
+    let mut subject_indent = String::new();
+    let mut has_bag = false;
+    let mut li_indent: Option<String> = None;
+    let mut inserted = false;
+
+    loop {
+        let event = reader.read_event()?;
+
+        if skip_depth > 0 {
+            match event {
+                Event::Start(_) => skip_depth += 1,
+                Event::End(_) => skip_depth -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+            continue;
+        }
+
+        match &event {
+            Event::Text(t) => {
+                let text = t.unescape()?;
+                if text.trim().is_empty() {
+                    pending.push_str(&text);
+                    continue;
+                }
+            }
+            // Written again after dc:subject.
+            Event::Start(e) if is_keywords_record(e.name().as_ref()) => {
+                pending.clear();
+                skip_depth = 1;
+                continue;
+            }
+            Event::Empty(e) if is_keywords_record(e.name().as_ref()) => {
+                pending.clear();
+                continue;
+            }
+            Event::Start(e) if replace_ours && is_ours(e) => {
+                pending.clear();
+                skip_depth = 1;
+                continue;
+            }
+            Event::Empty(e) if replace_ours && is_ours(e) => {
+                pending.clear();
+                continue;
+            }
+            Event::Start(e) if replace_ours && without_ours(e).is_some() => {
+                writer.get_mut().extend_from_slice(pending.as_bytes());
+                pending.clear();
+                writer.write_event(Event::Start(without_ours(e).unwrap()))?;
+                continue;
+            }
+            Event::Empty(e) if replace_ours && without_ours(e).is_some() => {
+                writer.get_mut().extend_from_slice(pending.as_bytes());
+                pending.clear();
+                writer.write_event(Event::Empty(without_ours(e).unwrap()))?;
+                continue;
+            }
+            Event::Start(e) if e.name().as_ref() == b"dc:subject" => {
+                in_subject = true;
+                subject_indent = pending.clone();
+            }
+            Event::Empty(e) if e.name().as_ref() == b"dc:subject" => {
+                // <dc:subject/>
+                let indent = format!("{} ", pending);
+                let subject = format!(
+                    "{}<dc:subject>{}{}{}</dc:subject>{}",
+                    pending,
+                    indent,
+                    bag(&indent, keywords),
+                    pending,
+                    keywords_record(&pending, &recorded)
+                );
+                writer.get_mut().extend_from_slice(subject.as_bytes());
+                pending.clear();
+                continue;
+            }
+            Event::End(e) if e.name().as_ref() == b"dc:subject" => {
+                if !has_bag {
+                    // <dc:subject></dc:subject>
+                    let indent = format!("{} ", subject_indent);
+                    let bag = format!("{}{}", indent, bag(&indent, keywords));
+                    writer.get_mut().extend_from_slice(bag.as_bytes());
+                }
+                writer.get_mut().extend_from_slice(pending.as_bytes());
+                pending.clear();
+                writer.write_event(event)?;
+                let record = keywords_record(&subject_indent, &recorded);
+                writer.get_mut().extend_from_slice(record.as_bytes());
+                in_subject = false;
+                continue;
+            }
+            Event::Empty(e) if in_subject && e.name().as_ref() == b"rdf:Bag" => {
+                // <rdf:Bag/>
+                has_bag = true;
+                let bag = format!("{}{}", pending, bag(&pending, keywords));
+                writer.get_mut().extend_from_slice(bag.as_bytes());
+                pending.clear();
+                continue;
+            }
+            Event::Start(e) if in_subject && e.name().as_ref() == b"rdf:Bag" => has_bag = true,
+            Event::Start(e) if in_subject && e.name().as_ref() == b"rdf:li" => {
+                let text = reader.read_text(e.name())?;
+                let keyword = quick_xml::escape::unescape(&text).map_err(quick_xml::Error::from)?;
+                if stale.iter().any(|k| **k == keyword.trim()) {
+                    pending.clear();
+                    continue;
+                }
+                li_indent = Some(pending.clone());
+                writer.get_mut().extend_from_slice(pending.as_bytes());
+                pending.clear();
+                writer.write_event(event.clone())?;
+                writer.get_mut().extend_from_slice(text.as_bytes());
+                writer.write_event(Event::End(e.to_end()))?;
+                continue;
+            }
+            Event::End(e) if in_subject && e.name().as_ref() == b"rdf:Bag" => {
+                let indent = li_indent.clone().unwrap_or_else(|| format!("{} ", pending));
+                for keyword in &added {
+                    let li = format!("{}<rdf:li>{}</rdf:li>", indent, escape(keyword));
+                    writer.get_mut().extend_from_slice(li.as_bytes());
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"rdf:RDF" && !inserted => {
+                let mut block = description.clone().unwrap_or_default();
+                if !has_subject && !keywords.is_empty() {
+                    block.push_str(&subject_description(keywords, &recorded));
+                }
+                writer.get_mut().extend_from_slice(block.as_bytes());
+                inserted = true;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        writer.get_mut().extend_from_slice(pending.as_bytes());
+        pending.clear();
+        writer.write_event(event)?;
+    }
+
+    if !inserted {
+        return Err(FilmError::Xmp("No rdf:RDF element found.".to_string()));
+    }
+
+    writer.get_mut().extend_from_slice(pending.as_bytes());
+    String::from_utf8(writer.into_inner()).map_err(|e| FilmError::Xmp(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXISTING: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+   xmp:Rating="3">
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>holiday &amp; beach</rdf:li>
+     <rdf:li>None</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    #[test]
+    fn creates_new_packet() {
+        let settings = FujifilmSettings::new();
+        let xmp = update_xmp(EMPTY, &settings, Some("Portra")).unwrap();
+
+        assert!(xmp.contains("<filmdetect:FilmSimulation>None</filmdetect:FilmSimulation>"));
+        assert!(xmp.contains("<filmdetect:Recipe>Portra</filmdetect:Recipe>"));
+        assert!(xmp.contains("<rdf:li>Portra</rdf:li>"));
+    }
+
+    #[test]
+    fn preserves_existing_content() {
+        let settings = FujifilmSettings::new();
+        let xmp = update_xmp(EXISTING, &settings, Some("Portra")).unwrap();
+
+        assert!(xmp.contains(r#"xmp:Rating="3""#));
+        assert!(xmp.contains("<rdf:li>holiday &amp; beach</rdf:li>"));
+        // "None" was there before; the recipe is the only keyword added.
+        assert_eq!(xmp.matches("<rdf:li>None</rdf:li>").count(), 1);
+        assert!(xmp.contains("     <rdf:li>Portra</rdf:li>\n    </rdf:Bag>"));
+        assert_eq!(xmp.matches("<dc:subject>").count(), 1);
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let settings = FujifilmSettings::new();
+        let once = update_xmp(EXISTING, &settings, Some("Portra")).unwrap();
+        let twice = update_xmp(&once, &settings, Some("Portra")).unwrap();
+
+        assert_eq!(once, twice);
+        assert_eq!(twice.matches("<filmdetect:FilmSimulation>").count(), 1);
+        assert_eq!(twice.matches("<filmdetect:Keywords").count(), 1);
+    }
+
+    #[test]
+    fn keeps_other_tools_data_in_shared_description() {
+        let shared = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:filmdetect="https://github.com/honza/film-detect/ns/1.0/"
+   xmp:Rating="4">
+   <filmdetect:FilmSimulation>Velvia</filmdetect:FilmSimulation>
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>holiday</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+        let mut settings = FujifilmSettings::new();
+        settings.film_mode = FilmMode::Eterna;
+        let xmp = update_xmp(shared, &settings, None).unwrap();
+
+        assert!(xmp.contains(r#"xmp:Rating="4""#));
+        assert!(xmp.contains("<rdf:li>holiday</rdf:li>"));
+        assert!(xmp.contains("<rdf:li>Eterna</rdf:li>"));
+        assert!(!xmp.contains("Velvia"));
+        assert_eq!(xmp.matches("<filmdetect:FilmSimulation>").count(), 1);
+        assert_eq!(update_xmp(&xmp, &settings, None).unwrap(), xmp);
+    }
+
+    #[test]
+    fn fills_an_empty_subject() {
+        let mut settings = FujifilmSettings::new();
+        settings.film_mode = FilmMode::Velvia;
+        for subject in ["<dc:subject/>", "<dc:subject><rdf:Bag/></dc:subject>"] {
+            let packet = EXISTING.replace(
+                &EXISTING[EXISTING.find("<dc:subject>").unwrap()
+                    ..EXISTING.find("</dc:subject>").unwrap() + "</dc:subject>".len()],
+                subject,
+            );
+            let xmp = update_xmp(&packet, &settings, Some("Portra")).unwrap();
+
+            assert_eq!(subject_keywords(&xmp).unwrap(), ["Velvia", "Portra"]);
+            assert_eq!(update_xmp(&xmp, &settings, Some("Portra")).unwrap(), xmp);
+        }
+    }
+
+    #[test]
+    fn removes_keywords_that_no_longer_apply() {
+        let mut settings = FujifilmSettings::new();
+        settings.film_mode = FilmMode::Velvia;
+        let before = update_xmp(EXISTING, &settings, Some("Portra")).unwrap();
+        assert_eq!(
+            subject_keywords(&before).unwrap(),
+            ["holiday & beach", "None", "Velvia", "Portra"]
+        );
+
+        // The recipe no longer matches and the film simulation changed.
+        settings.film_mode = FilmMode::Eterna;
+        assert_eq!(
+            stale_keywords(&before, &keywords(&settings, None)).unwrap(),
+            ["Velvia", "Portra"]
+        );
+        let after = update_xmp(&before, &settings, None).unwrap();
+        assert_eq!(
+            subject_keywords(&after).unwrap(),
+            ["holiday & beach", "None", "Eterna"]
+        );
+        assert_eq!(after.matches("<filmdetect:Keywords").count(), 1);
+
+        // "None" was already there, so it stays when it stops applying.
+        let merged = merge_keywords(&after, &[]).unwrap();
+        assert_eq!(
+            subject_keywords(&merged).unwrap(),
+            ["holiday & beach", "None"]
+        );
+        assert!(!merged.contains("<filmdetect:Keywords"));
+    }
+
+    #[test]
+    fn no_keyword_without_film_mode() {
+        let xmp = update_xmp(EMPTY, &FujifilmSettings::new(), None).unwrap();
+        assert!(!xmp.contains("<rdf:li>None</rdf:li>"));
+        assert!(!xmp.contains("<dc:subject>"));
+    }
+
+    #[test]
+    fn rejects_packet_without_rdf() {
+        let settings = FujifilmSettings::new();
+        assert!(matches!(
+            update_xmp("<x:xmpmeta/>", &settings, None),
+            Err(FilmError::Xmp(_))
+        ));
+    }
+}